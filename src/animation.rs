@@ -0,0 +1,259 @@
+//! Camera paths through keyframes, for rendering frame sequences such as orbits around the black hole.
+
+use std::cmp::Ordering;
+use std::str::FromStr;
+
+use glam::{Mat4, Quat, Vec3};
+use serde::{Deserialize, Serialize};
+
+use crate::camera;
+use crate::error::Error;
+
+/// Interpolation of positions, look-at targets and focal lengths between keyframes. Free orientations are always
+/// interpolated with slerp.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Interpolation {
+    /// Straight lines between keyframes, with a kink at each of them.
+    Linear,
+    /// Catmull–Rom spline through the keyframes, which keeps the velocity continuous.
+    #[default]
+    CatmullRom,
+}
+
+impl FromStr for Interpolation {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "linear" => Ok(Interpolation::Linear),
+            "catmull-rom" => Ok(Interpolation::CatmullRom),
+            _ => Err(Error::UnknownOption {
+                name: "interpolation",
+                value: s.to_string(),
+            }),
+        }
+    }
+}
+
+/// Direction of the camera at a keyframe.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Orientation {
+    /// Looking at `target` with `up` towards the top of the image, see [`camera::look_at`].
+    LookAt { target: Vec3, up: Vec3 },
+    /// Rotation from the camera frame of [`crate::render::Renderer::set_view`] to the world.
+    Rotation(Quat),
+}
+
+/// Camera at a point in time.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CameraKeyframe {
+    /// Time in seconds.
+    pub time: f32,
+    pub position: Vec3,
+    pub orientation: Orientation,
+    /// Focal length in units of half the image height.
+    pub focal_length: f32,
+}
+
+/// Smooth camera path through keyframes.
+///
+/// If every keyframe looks at a target, the targets are interpolated like the positions so that the camera keeps
+/// looking at them, e.g. at the black hole during an orbit. Otherwise the orientations are slerped.
+#[derive(Clone, Debug)]
+pub struct CameraPath {
+    keyframes: Vec<CameraKeyframe>,
+    rotations: Vec<Quat>,
+    // Targets and up directions, if every keyframe looks at a target
+    targets: Option<Vec<(Vec3, Vec3)>>,
+    interpolation: Interpolation,
+}
+
+impl CameraPath {
+    /// Creates a path through `keyframes`, which must be ordered by strictly increasing time.
+    pub fn new(keyframes: Vec<CameraKeyframe>, interpolation: Interpolation) -> Result<Self, Error> {
+        if keyframes.is_empty() {
+            return Err(Error::InvalidParameter {
+                name: "number of keyframes",
+                value: 0.0,
+            });
+        }
+
+        for pair in keyframes.windows(2) {
+            if pair[1].time.partial_cmp(&pair[0].time) != Some(Ordering::Greater) {
+                return Err(Error::InvalidParameter {
+                    name: "keyframe time",
+                    value: pair[1].time,
+                });
+            }
+        }
+
+        let rotations = keyframes
+            .iter()
+            .map(|keyframe| match keyframe.orientation {
+                Orientation::LookAt { target, up } => camera::look_at(keyframe.position, target, up)
+                    .map(|camera| Quat::from_mat4(&camera))
+                    .ok_or(Error::DegenerateCamera),
+                Orientation::Rotation(rotation) => Ok(rotation.normalize()),
+            })
+            .collect::<Result<_, _>>()?;
+
+        let targets = keyframes
+            .iter()
+            .map(|keyframe| match keyframe.orientation {
+                Orientation::LookAt { target, up } => Some((target, up)),
+                Orientation::Rotation(_) => None,
+            })
+            .collect();
+
+        Ok(Self {
+            keyframes,
+            rotations,
+            targets,
+            interpolation,
+        })
+    }
+
+    /// Time of the first keyframe.
+    pub fn start(&self) -> f32 {
+        self.keyframes[0].time
+    }
+
+    /// Time from the first to the last keyframe.
+    pub fn duration(&self) -> f32 {
+        self.keyframes[self.keyframes.len() - 1].time - self.start()
+    }
+
+    /// Times of the frames of a sequence at `fps` frames per second, from the first keyframe up to the last one.
+    pub fn frame_times(&self, fps: f32) -> impl Iterator<Item = f32> + '_ {
+        let frames = (self.duration() * fps + 1e-3).floor() as u32 + 1;
+        (0..frames).map(move |frame| self.start() + frame as f32 / fps)
+    }
+
+    /// Camera matrix, position and focal length at `time` for [`crate::render::Renderer::set_view`]. Times outside
+    /// the path are clamped to its ends.
+    pub fn view(&self, time: f32) -> (Mat4, Vec3, f32) {
+        let keyframes = &self.keyframes;
+        let last = keyframes.len() - 1;
+        let i = keyframes[..last]
+            .partition_point(|keyframe| keyframe.time <= time)
+            .saturating_sub(1);
+        let j = (i + 1).min(last);
+
+        let span = keyframes[j].time - keyframes[i].time;
+        let s = if span > 0.0 {
+            ((time - keyframes[i].time) / span).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+
+        let position = self.interpolate(i, s, |k| keyframes[k].position);
+        let focal_length = self.interpolate(i, s, |k| Vec3::splat(keyframes[k].focal_length)).x;
+
+        let camera = self
+            .targets
+            .as_ref()
+            .and_then(|targets| {
+                let target = self.interpolate(i, s, |k| targets[k].0);
+                let up = self.interpolate(i, s, |k| targets[k].1);
+                camera::look_at(position, target, up)
+            })
+            .unwrap_or_else(|| Mat4::from_quat(self.rotations[i].slerp(self.rotations[j], s)));
+
+        (camera, position, focal_length.max(f32::EPSILON))
+    }
+
+    /// Interpolates a value of the keyframes, given by their index, between keyframe `i` and the next one, at the
+    /// fraction `s` of the way.
+    fn interpolate(&self, i: usize, s: f32, value: impl Fn(usize) -> Vec3) -> Vec3 {
+        let keyframes = &self.keyframes;
+        let last = keyframes.len() - 1;
+        let j = (i + 1).min(last);
+        let (p0, p1) = (value(i), value(j));
+
+        match self.interpolation {
+            Interpolation::Linear => p0.lerp(p1, s),
+            Interpolation::CatmullRom if i == j => p0,
+            Interpolation::CatmullRom => {
+                // Tangents from the neighbouring keyframes, which handles uneven spacing in time
+                let tangent = |k: usize| {
+                    let (a, b) = (k.saturating_sub(1), (k + 1).min(last));
+                    (value(b) - value(a)) / (keyframes[b].time - keyframes[a].time)
+                };
+                let span = keyframes[j].time - keyframes[i].time;
+
+                let (s2, s3) = (s * s, s * s * s);
+                (2.0 * s3 - 3.0 * s2 + 1.0) * p0
+                    + (s3 - 2.0 * s2 + s) * span * tangent(i)
+                    + (-2.0 * s3 + 3.0 * s2) * p1
+                    + (s3 - s2) * span * tangent(j)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_2;
+
+    use super::*;
+
+    fn orbit(interpolation: Interpolation) -> CameraPath {
+        let keyframes = (0..5)
+            .map(|i| {
+                let angle = i as f32 * FRAC_PI_2;
+                CameraKeyframe {
+                    time: i as f32,
+                    position: Vec3::new(20.0 * angle.cos(), 20.0 * angle.sin(), 2.0),
+                    orientation: Orientation::LookAt {
+                        target: Vec3::ZERO,
+                        up: Vec3::Z,
+                    },
+                    focal_length: 1.5 + i as f32,
+                }
+            })
+            .collect();
+
+        CameraPath::new(keyframes, interpolation).unwrap()
+    }
+
+    #[test]
+    fn paths_pass_through_keyframes() {
+        for interpolation in [Interpolation::Linear, Interpolation::CatmullRom] {
+            let path = orbit(interpolation);
+            assert_eq!(path.frame_times(2.0).count(), 9);
+
+            let (camera, position, focal_length) = path.view(1.0);
+            assert!((position - Vec3::new(0.0, 20.0, 2.0)).length() < 1e-4, "{position}");
+            assert!((focal_length - 2.5).abs() < 1e-5);
+
+            // The camera keeps looking at the black hole
+            let forward = camera.z_axis.truncate();
+            assert!(forward.dot(-position.normalize()) > 0.9999, "{forward}");
+        }
+
+        // The spline stays closer to the circle halfway between keyframes than a straight line
+        let linear = orbit(Interpolation::Linear).view(0.5).1.truncate().length();
+        let spline = orbit(Interpolation::CatmullRom).view(0.5).1.truncate().length();
+        assert!((spline - 20.0).abs() < (linear - 20.0).abs(), "{linear} {spline}");
+    }
+
+    #[test]
+    fn free_orientations_are_slerped() {
+        let keyframe = |time: f32, yaw: f32| CameraKeyframe {
+            time,
+            position: Vec3::ZERO,
+            orientation: Orientation::Rotation(Quat::from_mat4(&camera::from_euler(yaw, 0.0, 0.0))),
+            focal_length: 1.5,
+        };
+        let path = CameraPath::new(
+            vec![keyframe(0.0, 0.0), keyframe(2.0, FRAC_PI_2)],
+            Interpolation::Linear,
+        )
+        .unwrap();
+
+        let expected = camera::from_euler(0.25 * FRAC_PI_2, 0.0, 0.0);
+        assert!(path.view(0.5).0.abs_diff_eq(expected, 1e-5));
+        assert!(CameraPath::new(vec![keyframe(1.0, 0.0), keyframe(1.0, 0.0)], Interpolation::Linear).is_err());
+    }
+}
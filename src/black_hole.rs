@@ -0,0 +1,236 @@
+use crate::error::Error;
+use crate::metric::Metric;
+
+/// Parameters of a Kerr-Newman black hole in geometrized units (G = c = 1).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BlackHole {
+    /// Mass `M`.
+    pub mass: f32,
+    /// Spin parameter `a = J/M`, the angular momentum per unit mass.
+    pub spin: f32,
+    /// Electric charge `Q`.
+    pub charge: f32,
+}
+
+impl BlackHole {
+    pub fn new(mass: f32, spin: f32, charge: f32) -> Self {
+        Self { mass, spin, charge }
+    }
+
+    /// Returns the parameters with the spin and charge that `metric` does not support set to zero.
+    pub fn restrict_to(&self, metric: Metric) -> Self {
+        Self {
+            mass: self.mass,
+            spin: if metric.has_spin() { self.spin } else { 0.0 },
+            charge: if metric.has_charge() { self.charge } else { 0.0 },
+        }
+    }
+
+    /// The outer event horizon `r+ = M + sqrt(M² - a² - Q²)`, or `None` for a naked singularity.
+    pub fn outer_horizon(&self) -> Option<f32> {
+        let d = self.mass * self.mass - self.spin * self.spin - self.charge * self.charge;
+        (d >= 0.0).then(|| self.mass + d.sqrt())
+    }
+
+    /// The inner (Cauchy) horizon `r- = M - sqrt(M² - a² - Q²)`, or `None` for a naked singularity.
+    pub fn inner_horizon(&self) -> Option<f32> {
+        let d = self.mass * self.mass - self.spin * self.spin - self.charge * self.charge;
+        (d >= 0.0).then(|| self.mass - d.sqrt())
+    }
+
+    /// Outer boundary of the ergosphere `M + sqrt(M² - a² cos²θ - Q²)` at polar angle `θ`, or `None` where it does
+    /// not exist.
+    pub fn ergosphere(&self, polar_angle: f32) -> Option<f32> {
+        let cos = polar_angle.cos();
+        let d = self.mass * self.mass - self.spin * self.spin * cos * cos - self.charge * self.charge;
+        (d >= 0.0).then(|| self.mass + d.sqrt())
+    }
+
+    /// Radius of the circular photon orbit in the equatorial plane, or `None` if there is none outside the horizon.
+    ///
+    /// Like the other orbits below, it rotates in the direction of positive `φ`, which is prograde for positive spin.
+    pub fn photon_orbit(&self) -> Option<f32> {
+        let (m, a, q) = (self.mass as f64, self.spin as f64, self.charge as f64);
+
+        // Circular orbits become null where the denominator of their energy vanishes
+        let d = |r: f64| {
+            let mrq = (m * r - q * q).max(0.0);
+            r * r - 3.0 * m * r + 2.0 * q * q + 2.0 * a * mrq.sqrt()
+        };
+
+        let mut lo = self.outer_horizon().map_or(1e-3 * m, |r| r as f64);
+        let mut hi = 5.0 * m;
+        if d(lo) > 0.0 || d(hi) <= 0.0 {
+            return None;
+        }
+
+        for _ in 0..64 {
+            let mid = 0.5 * (lo + hi);
+            if d(mid) > 0.0 {
+                hi = mid;
+            } else {
+                lo = mid;
+            }
+        }
+
+        Some(hi as f32)
+    }
+
+    /// Angular velocity `dφ/dt` of the circular orbit in the equatorial plane at Boyer-Lindquist radius `r`.
+    pub fn keplerian_angular_velocity(&self, r: f32) -> f32 {
+        let s = (self.mass * r - self.charge * self.charge).max(0.0).sqrt();
+        s / (r * r + self.spin * s)
+    }
+
+    /// Specific energy `E` and angular momentum `L` of the circular orbit in the equatorial plane at
+    /// Boyer-Lindquist radius `r`, or `None` where no timelike circular orbit exists.
+    pub fn circular_orbit(&self, r: f32) -> Option<(f32, f32)> {
+        self.circular_orbit_f64(r as f64)
+            .map(|(energy, angular_momentum)| (energy as f32, angular_momentum as f32))
+    }
+
+    fn circular_orbit_f64(&self, r: f64) -> Option<(f64, f64)> {
+        let (m, a, q) = (self.mass as f64, self.spin as f64, self.charge as f64);
+
+        let mrq = m * r - q * q;
+        if mrq < 0.0 {
+            return None;
+        }
+
+        let s = mrq.sqrt();
+        let d = r * r - 3.0 * m * r + 2.0 * q * q + 2.0 * a * s;
+        if d <= 0.0 {
+            return None;
+        }
+
+        let denominator = r * d.sqrt();
+        let energy = (r * r - 2.0 * m * r + q * q + a * s) / denominator;
+        let angular_momentum = (s * (r * r + a * a) - 2.0 * a * m * r + a * q * q) / denominator;
+
+        Some((energy, angular_momentum))
+    }
+
+    /// Radius of the innermost stable circular orbit in the equatorial plane, or `None` if there is none
+    /// within `12M`.
+    pub fn isco(&self) -> Option<f32> {
+        let energy = |r: f64| self.circular_orbit_f64(r).map_or(f64::INFINITY, |(e, _)| e);
+
+        // The orbital energy diverges towards the photon orbit and has its only minimum at the ISCO, so a
+        // golden-section search converges to it from anywhere outside the horizon
+        let ratio = (5.0f64.sqrt() - 1.0) / 2.0;
+        let max_radius = 12.0 * self.mass as f64;
+        let mut lo = self.outer_horizon().unwrap_or(0.0) as f64;
+        let mut hi = max_radius;
+
+        for _ in 0..96 {
+            let c = hi - ratio * (hi - lo);
+            let d = lo + ratio * (hi - lo);
+
+            if energy(c) < energy(d) {
+                hi = d;
+            } else {
+                lo = c;
+            }
+        }
+
+        let r = 0.5 * (lo + hi);
+        (energy(r).is_finite() && r < 0.99 * max_radius).then_some(r as f32)
+    }
+
+    /// Whether these parameters describe a naked singularity, i.e. `a² + Q² > M²`.
+    pub fn is_naked_singularity(&self) -> bool {
+        self.spin * self.spin + self.charge * self.charge > self.mass * self.mass
+    }
+
+    /// Checks that the parameters are finite, the mass is positive and, unless `allow_naked` is set, that the
+    /// black hole has an event horizon.
+    pub fn validate(&self, allow_naked: bool) -> Result<(), Error> {
+        if !self.mass.is_finite() || self.mass <= 0.0 {
+            return Err(Error::InvalidParameter {
+                name: "mass",
+                value: self.mass,
+            });
+        }
+
+        if !self.spin.is_finite() {
+            return Err(Error::InvalidParameter {
+                name: "spin",
+                value: self.spin,
+            });
+        }
+
+        if !self.charge.is_finite() {
+            return Err(Error::InvalidParameter {
+                name: "charge",
+                value: self.charge,
+            });
+        }
+
+        if !allow_naked && self.is_naked_singularity() {
+            return Err(Error::NakedSingularity {
+                mass: self.mass,
+                spin: self.spin,
+                charge: self.charge,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for BlackHole {
+    fn default() -> Self {
+        Self {
+            mass: 1.0,
+            spin: 0.3,
+            charge: 0.2,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BlackHole;
+    use crate::error::Error;
+
+    #[test]
+    fn naked_singularities_are_rejected_unless_allowed() {
+        let naked = BlackHole::new(1.0, 0.8, 0.7);
+        assert!(matches!(naked.validate(false), Err(Error::NakedSingularity { .. })));
+        assert!(naked.validate(true).is_ok());
+
+        // a² + Q² = M² still has a (degenerate) horizon
+        assert!(BlackHole::new(1.0, 0.6, 0.8).validate(false).is_ok());
+        assert!(BlackHole::new(1.0, 1.0, 0.0).validate(false).is_ok());
+
+        assert!(matches!(
+            BlackHole::new(0.0, 0.0, 0.0).validate(true),
+            Err(Error::InvalidParameter { name: "mass", .. })
+        ));
+    }
+
+    /// ISCO of a Kerr black hole from Bardeen, Press and Teukolsky (1972).
+    fn kerr_isco(m: f32, a: f32) -> f32 {
+        let chi = a / m;
+        let z1 = 1.0 + (1.0 - chi * chi).cbrt() * ((1.0 + chi).cbrt() + (1.0 - chi).cbrt());
+        let z2 = (3.0 * chi * chi + z1 * z1).sqrt();
+        m * (3.0 + z2 - chi.signum() * ((3.0 - z1) * (3.0 + z1 + 2.0 * z2)).sqrt())
+    }
+
+    #[test]
+    fn isco_matches_known_radii() {
+        let cases = [
+            (BlackHole::new(1.0, 0.0, 0.0), 6.0),
+            (BlackHole::new(2.0, 0.0, 0.0), 12.0),
+            (BlackHole::new(1.0, 0.0, 1.0), 4.0),
+            (BlackHole::new(1.0, 0.5, 0.0), kerr_isco(1.0, 0.5)),
+            (BlackHole::new(1.0, 0.9, 0.0), kerr_isco(1.0, 0.9)),
+            (BlackHole::new(1.0, -0.9, 0.0), kerr_isco(1.0, -0.9)),
+        ];
+
+        for (black_hole, expected) in cases {
+            let isco = black_hole.isco().unwrap();
+            assert!((isco - expected).abs() < 1e-3, "{black_hole:?}: {isco} != {expected}");
+        }
+    }
+}
@@ -0,0 +1,28 @@
+use glam::{Mat3, Mat4, Quat, Vec3};
+
+/// Camera matrix at `position` looking at `target`, with `up` pointing towards the top of the image.
+///
+/// The columns of the matrix are the directions of the image's right, its bottom and the line of sight, as expected by
+/// [`crate::render::Renderer::set_view`]. Returns `None` if the camera is at the target or looks along `up`.
+pub fn look_at(position: Vec3, target: Vec3, up: Vec3) -> Option<Mat4> {
+    let forward = (target - position).try_normalize()?;
+    let right = forward.cross(up).try_normalize()?;
+    let down = forward.cross(right);
+
+    Some(Mat4::from_mat3(Mat3::from_cols(right, down, forward)))
+}
+
+/// Camera matrix for an orientation given by Euler angles in radians.
+///
+/// With all angles zero the camera looks along `+y` with `+z` up. The yaw turns it to the left around `z`, the pitch
+/// tilts it up and the roll turns it clockwise around the line of sight.
+pub fn from_euler(yaw: f32, pitch: f32, roll: f32) -> Mat4 {
+    let rotation = Quat::from_rotation_z(yaw) * Quat::from_rotation_x(pitch) * Quat::from_rotation_y(roll);
+
+    Mat4::from_quat(rotation) * Mat4::from_mat3(Mat3::from_cols(Vec3::X, Vec3::NEG_Z, Vec3::Y))
+}
+
+/// Focal length for a vertical field of view in radians, in units of half the image height.
+pub fn focal_length(vertical_fov: f32) -> f32 {
+    1.0 / (0.5 * vertical_fov).tan()
+}
@@ -0,0 +1,386 @@
+use std::path::PathBuf;
+
+use clap::error::ErrorKind;
+use clap::{Args, CommandFactory, Parser, Subcommand};
+use glam::Vec3;
+
+use black_hole::animation::Interpolation;
+use black_hole::disc::{DiscModel, Redshift};
+use black_hole::integrator::Integrator;
+use black_hole::metric::{Gradient, Metric};
+use black_hole::output::{Channel, ColorSpace};
+use black_hole::sky::SkyFilter;
+use black_hole::tonemap::ToneMapping;
+use black_hole::Scene;
+
+#[derive(Parser)]
+#[command(
+    version,
+    about = "Renders black holes by tracing photon geodesics",
+    args_conflicts_with_subcommands = true
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    #[command(flatten)]
+    pub render: RenderArgs,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Prints the horizons, ergosphere, ISCOs, photon orbits and shadow size of a black hole
+    Info {
+        #[arg(long, default_value_t = 1.0)]
+        mass: f32,
+        /// Spin parameter a = J/M
+        #[arg(long, default_value_t = 0.3, allow_negative_numbers = true)]
+        spin: f32,
+        #[arg(long, default_value_t = 0.2, allow_negative_numbers = true)]
+        charge: f32,
+        /// Angle between the spin axis and the line of sight of a distant observer, in degrees
+        #[arg(long, default_value_t = 90.0)]
+        inclination: f32,
+        /// Also print the points of the shadow boundary
+        #[arg(long)]
+        shadow: bool,
+    },
+}
+
+/// Settings of a render, with the black hole at the origin and its spin axis along `z`.
+///
+/// Every setting overrides the corresponding one of the scene file, or of the default scene.
+#[derive(Args)]
+pub struct RenderArgs {
+    /// Scene file to render, in TOML
+    #[arg(long, value_name = "FILE")]
+    pub scene: Option<PathBuf>,
+    /// Write the effective scene, including all overrides, to FILE
+    #[arg(long, value_name = "FILE")]
+    pub dump_scene: Option<PathBuf>,
+    /// Render on the CPU instead of the GPU
+    #[arg(long)]
+    pub cpu: bool,
+
+    /// Width of the image in pixels [default: 1920]
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..), help_heading = "Output")]
+    pub width: Option<u32>,
+    /// Height of the image in pixels [default: 1080]
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..), help_heading = "Output")]
+    pub height: Option<u32>,
+    /// Number of samples per pixel, each traced in its own frame [default: 16]
+    #[arg(short, long, value_parser = clap::value_parser!(u32).range(1..), help_heading = "Output")]
+    pub samples: Option<u32>,
+    /// Path of the rendered image, whose extension selects the format. OpenEXR (.exr) and Radiance HDR (.hdr) store
+    /// the linear radiance without tone mapping [default: black-hole.png]
+    #[arg(short, long, help_heading = "Output")]
+    pub output: Option<PathBuf>,
+    /// Tone mapping operator: exponential, linear (or none), reinhard, reinhard-extended, aces or agx
+    /// [default: exponential]
+    #[arg(long, help_heading = "Output")]
+    pub tone_mapping: Option<ToneMapping>,
+    /// Exposure in stops, added to the automatic exposure if that is enabled [default: 0]
+    #[arg(long, value_name = "EV", allow_hyphen_values = true, help_heading = "Output")]
+    pub exposure: Option<f32>,
+    /// Expose the luminance at PERCENTILE of the lit pixels to one [default: 99 when given without a value]
+    #[arg(long, value_name = "PERCENTILE", num_args = 0..=1, default_missing_value = "99", help_heading = "Output")]
+    pub auto_exposure: Option<f32>,
+    /// Luminance mapped to white by the reinhard-extended operator [default: 4]
+    #[arg(long, help_heading = "Output")]
+    pub white_point: Option<f32>,
+    /// Colour space of tone-mapped images: srgb, linear-srgb, display-p3 or rec2020. All but srgb are tagged with an ICC
+    /// profile, which PNG, TIFF and JPEG can hold [default: srgb]
+    #[arg(long, help_heading = "Output")]
+    pub color_space: Option<ColorSpace>,
+    /// Bits per channel of tone-mapped images, 8 or 16 for PNG and TIFF [default: 8]
+    #[arg(long, help_heading = "Output")]
+    pub bit_depth: Option<u8>,
+    /// Extra OpenEXR channels, as a comma-separated list of samples, drift, steps and termination
+    #[arg(long, value_delimiter = ',', help_heading = "Output")]
+    pub channels: Option<Vec<Channel>>,
+    /// Seed of the random sampling, for renders that can be averaged [default: 0]
+    #[arg(long, help_heading = "Output")]
+    pub seed: Option<u32>,
+    /// Also write the integration diagnostics next to the image
+    #[arg(long, overrides_with = "no_diagnostics", help_heading = "Output")]
+    pub diagnostics: bool,
+    /// Do not write the integration diagnostics, even if the scene file asks for them
+    #[arg(long, overrides_with = "diagnostics", help_heading = "Output")]
+    pub no_diagnostics: bool,
+    /// Draw the predicted shadow over the image and compare it with the rendered one
+    #[arg(long, overrides_with = "no_shadow_overlay", help_heading = "Output")]
+    pub shadow_overlay: bool,
+    /// Do not draw the predicted shadow, even if the scene file asks for it
+    #[arg(long, overrides_with = "shadow_overlay", help_heading = "Output")]
+    pub no_shadow_overlay: bool,
+
+    /// Position of the camera, as x,y,z [default: 1.5139699,-16.080126,2.293509]
+    #[arg(long, value_parser = parse_vec3, allow_hyphen_values = true, help_heading = "Camera")]
+    pub position: Option<Vec3>,
+    /// Point the camera looks at, as x,y,z [default: 0,0,0]
+    #[arg(long, value_parser = parse_vec3, allow_hyphen_values = true, help_heading = "Camera")]
+    pub look_at: Option<Vec3>,
+    /// Direction towards the top of the image when using --look-at, as x,y,z [default: 0,0,1]
+    #[arg(long, value_parser = parse_vec3, allow_hyphen_values = true, help_heading = "Camera")]
+    pub up: Option<Vec3>,
+    /// Orientation of the camera instead of --look-at, as yaw,pitch,roll in degrees from looking along +y
+    #[arg(
+        long,
+        value_parser = parse_vec3,
+        allow_hyphen_values = true,
+        conflicts_with_all = ["look_at", "up"],
+        help_heading = "Camera"
+    )]
+    pub orientation: Option<Vec3>,
+    /// Vertical field of view in degrees, instead of --focal-length
+    #[arg(long, conflicts_with = "focal_length", help_heading = "Camera")]
+    pub fov: Option<f32>,
+    /// Focal length in units of half the image height [default: 1.5]
+    #[arg(long, help_heading = "Camera")]
+    pub focal_length: Option<f32>,
+    /// Frames per second of the animation of the scene file [default: 24]
+    #[arg(long, help_heading = "Camera")]
+    pub fps: Option<f32>,
+    /// Interpolation between the keyframes of the animation: linear or catmull-rom [default: catmull-rom]
+    #[arg(long, help_heading = "Camera")]
+    pub interpolation: Option<Interpolation>,
+
+    /// Metric of the black hole: schwarzschild, reissner-nordstrom, kerr or kerr-newman [default: kerr-newman]
+    #[arg(long, help_heading = "Black hole")]
+    pub metric: Option<Metric>,
+    /// Mass M [default: 1]
+    #[arg(long, help_heading = "Black hole")]
+    pub mass: Option<f32>,
+    /// Spin parameter a = J/M [default: 0.3]
+    #[arg(long, allow_negative_numbers = true, help_heading = "Black hole")]
+    pub spin: Option<f32>,
+    /// Charge Q [default: 0.2]
+    #[arg(long, allow_negative_numbers = true, help_heading = "Black hole")]
+    pub charge: Option<f32>,
+    /// Allow parameters with a² + Q² > M², which have no event horizon
+    #[arg(long, overrides_with = "disallow_naked_singularities", help_heading = "Black hole")]
+    pub allow_naked_singularities: bool,
+    /// Reject naked singularities, even if the scene file allows them
+    #[arg(long, overrides_with = "allow_naked_singularities", help_heading = "Black hole")]
+    pub disallow_naked_singularities: bool,
+    /// Radius of the sphere in which light is lensed [default: 120]
+    #[arg(long, help_heading = "Black hole")]
+    pub lensing_radius: Option<f32>,
+
+    /// Render the accretion disc, even if the scene file disables it
+    #[arg(long, overrides_with = "no_disc", help_heading = "Scene")]
+    pub disc: bool,
+    /// Do not render the accretion disc
+    #[arg(long, overrides_with = "disc", help_heading = "Scene")]
+    pub no_disc: bool,
+    /// Model of the accretion disc: volumetric or thin [default: volumetric]
+    #[arg(long, help_heading = "Scene")]
+    pub disc_model: Option<DiscModel>,
+    /// Peak temperature of the thin disc in Kelvin [default: 8000]
+    #[arg(long, help_heading = "Scene")]
+    pub disc_temperature: Option<f32>,
+    /// Shift of the disc emission: off, specific or bolometric [default: bolometric]
+    #[arg(long, help_heading = "Scene")]
+    pub redshift: Option<Redshift>,
+    /// Render a skybox: the embedded one, the square faces right, left, top, bottom, front and back in a directory,
+    /// e.g. right.png or right.exr, or an equirectangular panorama with its poles on the spin axis, e.g. sky.hdr
+    #[arg(
+        long,
+        value_name = "PATH",
+        num_args = 0..=1,
+        overrides_with = "no_skybox",
+        help_heading = "Scene"
+    )]
+    pub skybox: Option<Option<PathBuf>>,
+    /// Do not render a sky, even if the scene file enables one
+    #[arg(long, overrides_with = "skybox", help_heading = "Scene")]
+    pub no_skybox: bool,
+    /// Rotation of the sky in degrees about the x, y and z axes, in that order, as x,y,z [default: 0,0,0]
+    #[arg(long, value_parser = parse_vec3, allow_hyphen_values = true, help_heading = "Scene")]
+    pub sky_rotation: Option<Vec3>,
+    /// Factor by which the radiance of the sky is multiplied, e.g. to bring an HDR panorama in range [default: 1]
+    #[arg(long, help_heading = "Scene")]
+    pub sky_intensity: Option<f32>,
+    /// Filtering of the sky: bilinear, or footprint to select mip levels from the divergence of neighbouring rays
+    /// [default: bilinear]
+    #[arg(long, help_heading = "Scene")]
+    pub sky_filter: Option<SkyFilter>,
+    /// Render a procedural sky of stars and a galactic band instead of a skybox
+    #[arg(long, overrides_with = "no_procedural_sky", help_heading = "Scene")]
+    pub procedural_sky: bool,
+    /// Render the skybox instead of a procedural sky, even if the scene file sets one
+    #[arg(
+        long,
+        overrides_with = "procedural_sky",
+        conflicts_with_all = ["star_seed", "star_density", "star_brightness", "galaxy_brightness"],
+        help_heading = "Scene"
+    )]
+    pub no_procedural_sky: bool,
+    /// Seed of the procedural stars, implies --procedural-sky [default: 0]
+    #[arg(long, help_heading = "Scene")]
+    pub star_seed: Option<u32>,
+    /// Average number of procedural stars per square degree, implies --procedural-sky [default: 2]
+    #[arg(long, help_heading = "Scene")]
+    pub star_density: Option<f32>,
+    /// Flux of the faintest procedural stars in square arcminutes of unit radiance, implies --procedural-sky
+    /// [default: 10]
+    #[arg(long, help_heading = "Scene")]
+    pub star_brightness: Option<f32>,
+    /// Radiance of the procedural galactic band, zero for none, implies --procedural-sky [default: 1]
+    #[arg(long, help_heading = "Scene")]
+    pub galaxy_brightness: Option<f32>,
+
+    /// Integration method: euler, rk4, dormand-prince or leapfrog (generalised, implicit) [default: euler]
+    #[arg(long, help_heading = "Integration")]
+    pub integrator: Option<Integrator>,
+    /// Local error tolerance of adaptive integrators [default: 0.0001]
+    #[arg(long, help_heading = "Integration")]
+    pub tolerance: Option<f32>,
+    /// Gradient of the Hamiltonian: analytic or finite-difference [default: analytic]
+    #[arg(long, help_heading = "Integration")]
+    pub gradient: Option<Gradient>,
+}
+
+impl RenderArgs {
+    /// Overrides the settings of `scene` that were given on the command line, failing for animation settings if the
+    /// scene has no animation.
+    pub fn apply(&self, scene: &mut Scene) -> Result<(), clap::Error> {
+        fn set<T: Clone>(target: &mut T, value: &Option<T>) {
+            if let Some(value) = value {
+                *target = value.clone();
+            }
+        }
+
+        let output = &mut scene.output;
+        set(&mut output.width, &self.width);
+        set(&mut output.height, &self.height);
+        set(&mut output.path, &self.output);
+        set(&mut output.tone_mapping, &self.tone_mapping);
+        set(&mut output.exposure, &self.exposure);
+        if self.auto_exposure.is_some() {
+            output.auto_exposure = self.auto_exposure;
+        }
+        set(&mut output.white_point, &self.white_point);
+        set(&mut output.color_space, &self.color_space);
+        set(&mut output.bit_depth, &self.bit_depth);
+        set(&mut output.channels, &self.channels);
+        set(&mut output.diagnostics, &flag(self.diagnostics, self.no_diagnostics));
+        set(
+            &mut output.shadow_overlay,
+            &flag(self.shadow_overlay, self.no_shadow_overlay),
+        );
+
+        let camera = &mut scene.camera;
+        set(&mut camera.position, &self.position.map(|v| v.to_array()));
+        set(&mut camera.look_at, &self.look_at.map(|v| v.to_array()));
+        set(&mut camera.up, &self.up.map(|v| v.to_array()));
+        if self.look_at.is_some() || self.up.is_some() {
+            camera.orientation = None;
+        }
+        if self.orientation.is_some() {
+            camera.orientation = self.orientation.map(|v| v.to_array());
+        }
+        if self.focal_length.is_some() {
+            camera.fov = None;
+        }
+        set(&mut camera.focal_length, &self.focal_length);
+        if self.fov.is_some() {
+            camera.fov = self.fov;
+        }
+
+        match &mut scene.animation {
+            Some(animation) => {
+                set(&mut animation.fps, &self.fps);
+                set(&mut animation.interpolation, &self.interpolation);
+            }
+            None if self.fps.is_some() || self.interpolation.is_some() => {
+                let option = if self.fps.is_some() { "--fps" } else { "--interpolation" };
+                return Err(Cli::command().error(
+                    ErrorKind::ArgumentConflict,
+                    format!("{option} needs a scene file with an [animation] table"),
+                ));
+            }
+            None => {}
+        }
+
+        let black_hole = &mut scene.black_hole;
+        set(&mut black_hole.metric, &self.metric);
+        set(&mut black_hole.mass, &self.mass);
+        set(&mut black_hole.spin, &self.spin);
+        set(&mut black_hole.charge, &self.charge);
+        set(
+            &mut black_hole.allow_naked_singularities,
+            &flag(self.allow_naked_singularities, self.disallow_naked_singularities),
+        );
+        set(&mut black_hole.lensing_radius, &self.lensing_radius);
+
+        let disc = &mut scene.disc;
+        set(&mut disc.enabled, &flag(self.disc, self.no_disc));
+        set(&mut disc.model, &self.disc_model);
+        set(&mut disc.temperature, &self.disc_temperature);
+        set(&mut disc.redshift, &self.redshift);
+
+        if let Some(path) = &self.skybox {
+            scene.sky.enabled = true;
+            if path.is_some() {
+                scene.sky.path.clone_from(path);
+            }
+        }
+        if self.no_skybox {
+            scene.sky.enabled = false;
+        }
+        set(&mut scene.sky.rotation, &self.sky_rotation.map(|v| v.to_array()));
+        set(&mut scene.sky.intensity, &self.sky_intensity);
+        set(&mut scene.sky.filter, &self.sky_filter);
+
+        let procedural = self.procedural_sky
+            || self.star_seed.is_some()
+            || self.star_density.is_some()
+            || self.star_brightness.is_some()
+            || self.galaxy_brightness.is_some();
+        if procedural {
+            scene.sky.enabled = true;
+            let sky = scene.sky.procedural.get_or_insert_with(Default::default);
+            set(&mut sky.seed, &self.star_seed);
+            set(&mut sky.density, &self.star_density);
+            set(&mut sky.brightness, &self.star_brightness);
+            set(&mut sky.galaxy, &self.galaxy_brightness);
+        }
+        if self.no_procedural_sky {
+            scene.sky.procedural = None;
+        }
+
+        let sampling = &mut scene.sampling;
+        set(&mut sampling.samples, &self.samples);
+        set(&mut sampling.seed, &self.seed);
+        set(&mut sampling.integrator, &self.integrator);
+        set(&mut sampling.tolerance, &self.tolerance);
+        set(&mut sampling.gradient, &self.gradient);
+
+        Ok(())
+    }
+}
+
+/// Value of a flag that can be turned on with `yes` and off with `no`, of which clap keeps the last one given.
+fn flag(yes: bool, no: bool) -> Option<bool> {
+    match (yes, no) {
+        (true, _) => Some(true),
+        (_, true) => Some(false),
+        _ => None,
+    }
+}
+
+fn parse_vec3(s: &str) -> Result<Vec3, String> {
+    let components = s
+        .split(',')
+        .map(|c| c.trim().parse::<f32>().map_err(|e| format!("{c:?}: {e}")))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    match components[..] {
+        [x, y, z] if x.is_finite() && y.is_finite() && z.is_finite() => Ok(Vec3::new(x, y, z)),
+        [_, _, _] => Err("components must be finite".to_string()),
+        _ => Err(format!(
+            "expected three comma-separated numbers, got {}",
+            components.len()
+        )),
+    }
+}
@@ -0,0 +1,39 @@
+use glam::{Vec3, Vec4, Vec4Swizzles};
+
+use super::geodesic;
+use super::volume;
+use crate::disc::Redshift;
+use crate::types::{Disc, Spacetime};
+
+// Brightness of the thin accretion disc at its peak flux
+const THIN_DISC_BRIGHTNESS: f32 = 4.0;
+
+/// Fraction of the step from `x0` to `x1` at which the ray crosses the thin disc, if it does.
+pub fn thin_disc_crossing(spacetime: &Spacetime, disc: &Disc, x0: Vec4, x1: Vec4) -> Option<f32> {
+    if x0.w * x1.w > 0.0 || x0.w == x1.w {
+        return None;
+    }
+
+    let t = x0.w / (x0.w - x1.w);
+    let r = geodesic::radius(spacetime, x0.lerp(x1, t).yzw());
+    if r < disc.isco.max(spacetime.horizon) || r > disc.outer_radius {
+        return None;
+    }
+
+    Some(t)
+}
+
+/// Radiance emitted by the optically thick thin disc at `x` towards the camera.
+pub fn thin_disc_emission(spacetime: &Spacetime, disc: &Disc, p: Vec4, x: Vec4, e_obs: f32) -> Vec3 {
+    let r = geodesic::radius(spacetime, x.yzw());
+    let s = ((r - disc.isco) / (disc.outer_radius - disc.isco)).clamp(0.0, 1.0) * 63.0;
+    let i = (s as usize).min(62);
+    let flux = disc.flux[i] + (disc.flux[i + 1] - disc.flux[i]) * (s - i as f32);
+
+    let mut g = 1.0;
+    if disc.redshift != Redshift::Off as u32 {
+        g = geodesic::redshift(spacetime, disc, p, x, e_obs);
+    }
+
+    THIN_DISC_BRIGHTNESS * flux * volume::emission(disc, disc.temperature * flux.powf(0.25), g)
+}
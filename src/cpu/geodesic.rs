@@ -0,0 +1,632 @@
+use std::f32::consts::FRAC_1_SQRT_2;
+use std::ops::{Add, Mul, Sub};
+
+use glam::{Mat2, Mat4, Vec2, Vec3, Vec4, Vec4Swizzles};
+
+use crate::diagnostics::Termination;
+use crate::integrator::Integrator;
+use crate::metric::Metric;
+use crate::types::{Disc, Integration, Spacetime};
+
+use super::smoothstep;
+
+// Epsilon when approximating gradients
+const EPS: f32 = 0.005;
+
+/// Minimum timestep for spacetime pathtracer
+pub const DT_MIN: f32 = 0.01;
+// Maximum timestep for spacetime pathtracer
+const DT_MAX: f32 = 0.1;
+
+// Bounds of the adaptive step scale
+const STEP_SCALE_MIN: f32 = 0.015625;
+const STEP_SCALE_MAX: f32 = 8.0;
+// Maximum number of rejected steps before a step is accepted regardless of its error
+const MAX_REJECTIONS: u32 = 8;
+// Fixed-point iterations solving each implicit stage of the generalised leapfrog
+const LEAPFROG_ITERATIONS: u32 = 4;
+
+/// Kerr-Schild decomposition `g = η + f k⊗k` of the metric, with the spatial derivatives of `f` and `k`.
+pub struct KerrSchild {
+    pub f: f32,
+    pub k: Vec4,
+    pub df: Vec3,
+    /// `dk[i]` holds `∂k/∂x_i`.
+    pub dk: [Vec4; 3],
+}
+
+/// Momentum and position of a photon.
+#[derive(Clone, Copy, Debug)]
+pub struct Phase {
+    pub p: Vec4,
+    pub x: Vec4,
+}
+
+impl Add for Phase {
+    type Output = Phase;
+
+    fn add(self, rhs: Phase) -> Phase {
+        Phase {
+            p: self.p + rhs.p,
+            x: self.x + rhs.x,
+        }
+    }
+}
+
+impl Sub for Phase {
+    type Output = Phase;
+
+    fn sub(self, rhs: Phase) -> Phase {
+        Phase {
+            p: self.p - rhs.p,
+            x: self.x - rhs.x,
+        }
+    }
+}
+
+impl Mul<f32> for Phase {
+    type Output = Phase;
+
+    fn mul(self, rhs: f32) -> Phase {
+        Phase {
+            p: self.p * rhs,
+            x: self.x * rhs,
+        }
+    }
+}
+
+fn is_spherical(spacetime: &Spacetime) -> bool {
+    spacetime.metric == Metric::Schwarzschild as u32 || spacetime.metric == Metric::ReissnerNordstrom as u32
+}
+
+fn outer(a: Vec4, b: Vec4) -> Mat4 {
+    Mat4::from_cols(a.x * b, a.y * b, a.z * b, a.w * b)
+}
+
+/// Boyer-Lindquist radius of a point given in Kerr-Schild coordinates.
+pub fn radius(spacetime: &Spacetime, p: Vec3) -> f32 {
+    let a = spacetime.spin;
+    let rho = p.dot(p) - a * a;
+    let r2 = 0.5 * (rho + (rho * rho + 4.0 * a * a * p.z * p.z).sqrt());
+    r2.sqrt()
+}
+
+/// Metric tensor at `x` in Kerr-Schild coordinates.
+pub fn metric(spacetime: &Spacetime, x: Vec4) -> Mat4 {
+    let (a, m, q) = (spacetime.spin, spacetime.mass, spacetime.charge);
+    let cdist = spacetime.lensing_radius;
+
+    let p = x.yzw();
+
+    let (f, k) = if is_spherical(spacetime) {
+        let r = p.length();
+        let f = smoothstep(cdist * 0.5, 0.0, r) * (2.0 * m * r - q * q) / (r * r);
+        (f, (p / r).extend(1.0).wxyz())
+    } else {
+        let r = radius(spacetime, p);
+        let r2 = r * r;
+        let k = Vec4::new(
+            1.0,
+            (r * p.x + a * p.y) / (r2 + a * a),
+            (r * p.y - a * p.x) / (r2 + a * a),
+            p.z / r,
+        );
+        let f = smoothstep(cdist * 0.5, 0.0, r) * r2 * (2.0 * m * r - q * q) / (r2 * r2 + a * a * p.z * p.z);
+        (f, k)
+    };
+
+    f * outer(k, k) + Mat4::from_diagonal(Vec4::new(-1.0, 1.0, 1.0, 1.0))
+}
+
+// Derivative of smoothstep(cdist * 0.5, 0.0, r) with respect to r.
+fn lensing_falloff_derivative(spacetime: &Spacetime, r: f32) -> f32 {
+    let cdist = spacetime.lensing_radius;
+    let t = (1.0 - 2.0 * r / cdist).clamp(0.0, 1.0);
+    -12.0 * t * (1.0 - t) / cdist
+}
+
+fn kerr_schild_spherical(spacetime: &Spacetime, p: Vec3) -> KerrSchild {
+    let (m, q) = (spacetime.mass, spacetime.charge);
+
+    let r = p.length();
+    let n = p / r;
+
+    let s = smoothstep(spacetime.lensing_radius * 0.5, 0.0, r);
+    let h = (2.0 * m * r - q * q) / (r * r);
+    let dh = (2.0 * q * q - 2.0 * m * r) / (r * r * r);
+
+    KerrSchild {
+        f: s * h,
+        k: n.extend(1.0).wxyz(),
+        df: (lensing_falloff_derivative(spacetime, r) * h + s * dh) * n,
+        dk: [Vec3::X, Vec3::Y, Vec3::Z].map(|e| ((e - e.dot(n) * n) / r).extend(0.0).wxyz()),
+    }
+}
+
+fn kerr_schild_kerr_newman(spacetime: &Spacetime, p: Vec3) -> KerrSchild {
+    let (a, m, q) = (spacetime.spin, spacetime.mass, spacetime.charge);
+
+    let rho = p.dot(p) - a * a;
+    let sq = (rho * rho + 4.0 * a * a * p.z * p.z).sqrt();
+    let r2 = 0.5 * (rho + sq);
+    let r = r2.sqrt();
+    let dr = (r2 * p + Vec3::new(0.0, 0.0, a * a * p.z)) / (r * sq);
+
+    let ra = r2 + a * a;
+    let k = Vec4::new(1.0, (r * p.x + a * p.y) / ra, (r * p.y - a * p.x) / ra, p.z / r);
+    let dk1 = (p.x * dr + Vec3::new(r, a, 0.0) - 2.0 * r * k.y * dr) / ra;
+    let dk2 = (p.y * dr + Vec3::new(-a, r, 0.0) - 2.0 * r * k.z * dr) / ra;
+    let dk3 = (Vec3::Z - k.w * dr) / r;
+
+    let s = smoothstep(spacetime.lensing_radius * 0.5, 0.0, r);
+    let num = r2 * (2.0 * m * r - q * q);
+    let den = r2 * r2 + a * a * p.z * p.z;
+    let dnum = (6.0 * m * r2 - 2.0 * q * q * r) * dr;
+    let dden = 4.0 * r2 * r * dr + Vec3::new(0.0, 0.0, 2.0 * a * a * p.z);
+    let h = num / den;
+    let dh = (dnum * den - num * dden) / (den * den);
+
+    KerrSchild {
+        f: s * h,
+        k,
+        df: lensing_falloff_derivative(spacetime, r) * h * dr + s * dh,
+        dk: [
+            Vec4::new(0.0, dk1.x, dk2.x, dk3.x),
+            Vec4::new(0.0, dk1.y, dk2.y, dk3.y),
+            Vec4::new(0.0, dk1.z, dk2.z, dk3.z),
+        ],
+    }
+}
+
+pub fn kerr_schild(spacetime: &Spacetime, p: Vec3) -> KerrSchild {
+    if is_spherical(spacetime) {
+        kerr_schild_spherical(spacetime, p)
+    } else {
+        kerr_schild_kerr_newman(spacetime, p)
+    }
+}
+
+/// Value of the Hamiltonian `g^{μν} p_μ p_ν`, which stays constant along an exactly integrated geodesic.
+pub fn hamiltonian(spacetime: &Spacetime, p: Vec4, x: Vec4) -> f32 {
+    let ks = kerr_schild(spacetime, x.yzw());
+    let kp = Vec4::new(-ks.k.x, ks.k.y, ks.k.z, ks.k.w).dot(p);
+    p.dot(Vec4::new(-p.x, p.y, p.z, p.w)) - ks.f * kp * kp
+}
+
+pub fn null_momentum(spacetime: &Spacetime, v: Vec3, x: Vec3) -> Vec4 {
+    2.0 * metric(spacetime, x.extend(0.0).wxyz()) * v.extend(1.0).wxyz()
+}
+
+pub fn dxdt_from_momentum(spacetime: &Spacetime, p: Vec4, x: Vec4) -> Vec4 {
+    metric(spacetime, x).inverse() * p
+}
+
+/// Magnitude of the momentum above which a photon has diverged, as it has piled up within a minimum step of the
+/// horizon.
+///
+/// Photons enter the lensing sphere with unit momentum, so their energy is at most `1/√2`. Along the spin axis, an
+/// outgoing photon is blueshifted by `(1 + f) / (1 - f)`, with the Kerr-Schild `f` including the lensing falloff, and
+/// the bound is its momentum at [`DT_MIN`] outside the horizon. Without a horizon photons do not pile up, and only
+/// momenta that are no longer finite diverge.
+pub fn divergence_momentum(spacetime: &Spacetime) -> f32 {
+    if spacetime.horizon <= 0.0 {
+        return f32::INFINITY;
+    }
+
+    let f = kerr_schild(spacetime, Vec3::new(0.0, 0.0, spacetime.horizon + DT_MIN)).f;
+    let blueshift = (1.0 + f) / (1.0 - f);
+    FRAC_1_SQRT_2 * (1.0 + blueshift * blueshift).sqrt()
+}
+
+/// Four-velocity of an observer at rest in the Kerr-Schild coordinates, clamped inside the ergosphere.
+pub fn static_velocity(spacetime: &Spacetime, x: Vec4) -> Vec4 {
+    let g = metric(spacetime, x);
+    Vec4::new((-g.x_axis.x).max(1e-4).sqrt().recip(), 0.0, 0.0, 0.0)
+}
+
+/// Angular velocity `dφ/dt` of the prograde circular orbit in the equatorial plane at Boyer-Lindquist radius `r`.
+fn keplerian_angular_velocity(spacetime: &Spacetime, r: f32) -> f32 {
+    let (a, m, q) = (spacetime.spin, spacetime.mass, spacetime.charge);
+
+    let s = (m * r - q * q).max(0.0).sqrt();
+    s / (r * r + a * s)
+}
+
+/// Four-velocity of the gas in the accretion disc, on circular orbits outside the ISCO and plunging inside it.
+pub fn disc_velocity(spacetime: &Spacetime, disc: &Disc, x: Vec4) -> Vec4 {
+    let a = spacetime.spin;
+    let g = metric(spacetime, x);
+
+    let p = x.yzw();
+    let r = radius(spacetime, p);
+
+    // Killing vectors of time translation and rotation around the spin axis
+    let t = Vec4::X;
+    let phi = Vec4::new(0.0, -p.y, p.x, 0.0);
+
+    if r >= disc.isco {
+        let v = t + keplerian_angular_velocity(spacetime, r) * phi;
+        let norm = -(g * v).dot(v);
+        if norm <= 0.0 {
+            return static_velocity(spacetime, x);
+        }
+
+        return v / norm.sqrt();
+    }
+
+    // Solve for u = α t + β φ + γ n with g(u, t) = -E, g(u, φ) = L and g(u, u) = -1, where n points radially
+    let n = Vec4::new(
+        0.0,
+        (r * p.x + a * p.y) / (r * r + a * a),
+        (r * p.y - a * p.x) / (r * r + a * a),
+        p.z / r,
+    );
+
+    let gt = g * t;
+    let gphi = g * phi;
+    let h = Mat2::from_cols_array(&[gt.dot(t), gt.dot(phi), gphi.dot(t), gphi.dot(phi)]);
+    if h.determinant().abs() < 1e-6 {
+        return static_velocity(spacetime, x);
+    }
+
+    let h_inv = h.inverse();
+    let b = Vec2::new(-disc.isco_energy, disc.isco_angular_momentum);
+    let c = Vec2::new(gt.dot(n), gphi.dot(n));
+    let w = h_inv * b;
+    let d = h_inv * c;
+
+    let gamma = -((-1.0 - b.dot(w)) / ((g * n).dot(n) - c.dot(d))).max(0.0).sqrt();
+    let ab = w - gamma * d;
+    ab.x * t + ab.y * phi + gamma * n
+}
+
+/// Redshift factor `g = ν_obs / ν_emit` of light emitted by the disc at `x` and received with energy `e_obs`.
+pub fn redshift(spacetime: &Spacetime, disc: &Disc, p: Vec4, x: Vec4, e_obs: f32) -> f32 {
+    // Photons are traced backwards from the camera, so the emitted photon travels against the spatial momentum
+    let k = Vec4::new(p.x, -p.y, -p.z, -p.w);
+    e_obs / k.dot(disc_velocity(spacetime, disc, x))
+}
+
+fn lagrangian(spacetime: &Spacetime, dxdt: Vec4, x: Vec4) -> f32 {
+    (metric(spacetime, x) * dxdt).dot(dxdt)
+}
+
+/// Gradient of the Hamiltonian with respect to position, from exact derivatives of the metric.
+pub fn gradient_analytic(spacetime: &Spacetime, dxdt: Vec4, x: Vec4) -> Vec4 {
+    let ks = kerr_schild(spacetime, x.yzw());
+    let kv = ks.k.dot(dxdt);
+    let dkv = Vec3::new(dxdt.dot(ks.dk[0]), dxdt.dot(ks.dk[1]), dxdt.dot(ks.dk[2]));
+    -(ks.df * kv * kv + 2.0 * ks.f * kv * dkv).extend(0.0).wxyz()
+}
+
+/// Gradient of the Hamiltonian with respect to position, from forward differences of the Lagrangian.
+pub fn gradient_finite_difference(spacetime: &Spacetime, dxdt: Vec4, x: Vec4) -> Vec4 {
+    let l = lagrangian(spacetime, dxdt, x);
+    -(Vec4::new(
+        lagrangian(spacetime, dxdt, x + Vec4::X * EPS),
+        lagrangian(spacetime, dxdt, x + Vec4::Y * EPS),
+        lagrangian(spacetime, dxdt, x + Vec4::Z * EPS),
+        lagrangian(spacetime, dxdt, x + Vec4::W * EPS),
+    ) - Vec4::splat(l))
+        / EPS
+}
+
+/// Integrates a photon geodesic, tracking the heuristic timestep and adaptive step scale like the shader does.
+pub struct Geodesic<'a> {
+    spacetime: &'a Spacetime,
+    integration: &'a Integration,
+    finite_difference: bool,
+    pub dt: f32,
+    pub step_scale: f32,
+    /// Length of the last step taken by [`Geodesic::integrate`], which adaptive integrators may shrink.
+    pub accepted_step: f32,
+}
+
+impl<'a> Geodesic<'a> {
+    pub fn new(spacetime: &'a Spacetime, integration: &'a Integration, finite_difference: bool) -> Self {
+        Self {
+            spacetime,
+            integration,
+            finite_difference,
+            dt: DT_MIN,
+            step_scale: 1.0,
+            accepted_step: 0.0,
+        }
+    }
+
+    pub fn dhstep(&self, s: Phase, dt: f32) -> Phase {
+        let (g_inv, dhdq) = if self.finite_difference {
+            let g_inv = metric(self.spacetime, s.x).inverse();
+            (g_inv, gradient_finite_difference(self.spacetime, g_inv * s.p, s.x))
+        } else {
+            // Since k is null, the inverse metric is η - f k'⊗k' with k' = ηk.
+            let ks = kerr_schild(self.spacetime, s.x.yzw());
+            let ku = Vec4::new(-ks.k.x, ks.k.y, ks.k.z, ks.k.w);
+            let g_inv = Mat4::from_diagonal(Vec4::new(-1.0, 1.0, 1.0, 1.0)) - ks.f * outer(ku, ku);
+            (g_inv, gradient_analytic(self.spacetime, g_inv * s.p, s.x))
+        };
+
+        Phase {
+            p: -dhdq * dt,
+            x: 2.0 * g_inv * s.p * dt,
+        }
+    }
+
+    fn rk4(&self, s: Phase, h: f32) -> Phase {
+        let k1 = self.dhstep(s, h);
+        let k2 = self.dhstep(s + k1 * 0.5, h);
+        let k3 = self.dhstep(s + k2 * 0.5, h);
+        let k4 = self.dhstep(s + k3, h);
+        s + (k1 + k2 * 2.0 + k3 * 2.0 + k4) * (1.0 / 6.0)
+    }
+
+    /// Generalised leapfrog (implicit Störmer-Verlet), which is symplectic and time-reversible for the
+    /// non-separable geodesic Hamiltonian. The half kick is implicit in the momentum and the drift in the position,
+    /// both solved by fixed-point iteration.
+    fn leapfrog(&self, s: Phase, h: f32) -> Phase {
+        let mut p_half = s.p;
+        for _ in 0..LEAPFROG_ITERATIONS {
+            p_half = s.p + 0.5 * self.dhstep(Phase { p: p_half, x: s.x }, h).p;
+        }
+
+        let v0 = self.dhstep(Phase { p: p_half, x: s.x }, h).x;
+        let mut x = s.x + v0;
+        for _ in 0..LEAPFROG_ITERATIONS {
+            x = s.x + 0.5 * (v0 + self.dhstep(Phase { p: p_half, x }, h).x);
+        }
+
+        let p = p_half + 0.5 * self.dhstep(Phase { p: p_half, x }, h).p;
+        Phase { p, x }
+    }
+
+    fn dormand_prince(&mut self, s: Phase, h0: f32) -> Phase {
+        let mut h = h0;
+
+        for i in 0..=MAX_REJECTIONS {
+            let k1 = self.dhstep(s, h);
+            let k2 = self.dhstep(s + k1 * (1.0 / 5.0), h);
+            let k3 = self.dhstep(s + k1 * (3.0 / 40.0) + k2 * (9.0 / 40.0), h);
+            let k4 = self.dhstep(s + k1 * (44.0 / 45.0) - k2 * (56.0 / 15.0) + k3 * (32.0 / 9.0), h);
+            let k5 = self.dhstep(
+                s + k1 * (19372.0 / 6561.0) - k2 * (25360.0 / 2187.0) + k3 * (64448.0 / 6561.0) - k4 * (212.0 / 729.0),
+                h,
+            );
+            let k6 = self.dhstep(
+                s + k1 * (9017.0 / 3168.0) - k2 * (355.0 / 33.0) + k3 * (46732.0 / 5247.0) + k4 * (49.0 / 176.0)
+                    - k5 * (5103.0 / 18656.0),
+                h,
+            );
+            let y = s + k1 * (35.0 / 384.0) + k3 * (500.0 / 1113.0) + k4 * (125.0 / 192.0) - k5 * (2187.0 / 6784.0)
+                + k6 * (11.0 / 84.0);
+            let k7 = self.dhstep(y, h);
+
+            // Difference between the fifth and embedded fourth order solutions
+            let e = k1 * (71.0 / 57600.0) - k3 * (71.0 / 16695.0) + k4 * (71.0 / 1920.0) - k5 * (17253.0 / 339200.0)
+                + k6 * (22.0 / 525.0)
+                - k7 * (1.0 / 40.0);
+            let err = (e.p.abs() / (1.0 + y.p.abs())).max(e.x.abs() / (1.0 + y.x.abs()));
+            let err_max = err.max_element() / self.integration.tolerance;
+
+            let factor = 0.9 * err_max.max(1e-10).powf(-0.2);
+
+            if err_max <= 1.0 || self.step_scale <= STEP_SCALE_MIN || i == MAX_REJECTIONS {
+                self.step_scale = (self.step_scale * factor.clamp(0.2, 5.0)).clamp(STEP_SCALE_MIN, STEP_SCALE_MAX);
+                self.accepted_step = h;
+                return y;
+            }
+
+            let shrink = factor.max(0.2);
+            self.step_scale = (self.step_scale * shrink).max(STEP_SCALE_MIN);
+            h *= shrink;
+        }
+
+        self.accepted_step = 0.0;
+        s
+    }
+
+    pub fn integrate(&mut self, s: Phase, h: f32) -> Phase {
+        self.accepted_step = h;
+
+        match self.integration.method {
+            m if m == Integrator::Rk4 as u32 => self.rk4(s, h),
+            m if m == Integrator::DormandPrince as u32 => self.dormand_prince(s, h),
+            m if m == Integrator::Leapfrog as u32 => self.leapfrog(s, h),
+            _ => s + self.dhstep(s, h),
+        }
+    }
+
+    /// Updates the timestep for the current position and returns why the path should terminate, if it should.
+    pub fn update_dt(&mut self, p: Vec4, x: Vec4) -> Option<Termination> {
+        let cdist = self.spacetime.lensing_radius;
+        let horizon = self.spacetime.horizon;
+
+        let r = radius(self.spacetime, x.yzw());
+
+        self.dt = DT_MIN + (DT_MAX - DT_MIN) * ((r - horizon).max(0.0) / cdist);
+
+        if r < horizon {
+            return Some(Termination::Captured);
+        }
+
+        // Also catches momenta that are no longer finite
+        let momentum = p.length();
+        if momentum.is_nan() || momentum > self.spacetime.max_momentum {
+            return Some(Termination::Diverged);
+        }
+
+        if x.yzw().length() > cdist {
+            return Some(Termination::Escaped);
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytemuck::Zeroable;
+    use glam::{Vec3, Vec4, Vec4Swizzles};
+
+    use std::f32::consts::FRAC_1_SQRT_2;
+
+    use super::{
+        disc_velocity, divergence_momentum, gradient_analytic, gradient_finite_difference, hamiltonian, metric,
+        null_momentum, Geodesic, Phase, DT_MIN,
+    };
+    use crate::black_hole::BlackHole;
+    use crate::integrator::Integrator;
+    use crate::metric::Metric;
+    use crate::types::{Disc, Integration, Spacetime};
+
+    #[test]
+    fn divergence_momentum_is_that_of_an_outgoing_photon() {
+        for (spin, charge) in [(0.0, 0.0), (0.9, 0.0), (0.3, 0.2), (0.6, 0.7)] {
+            let black_hole = BlackHole::new(1.0, spin, charge);
+            let st = Spacetime {
+                mass: 1.0,
+                spin,
+                charge,
+                lensing_radius: 120.0,
+                metric: Metric::KerrNewman as u32,
+                ..Spacetime::zeroed()
+            };
+
+            let st = Spacetime {
+                horizon: black_hole.outer_horizon().unwrap(),
+                ..st
+            };
+
+            // Outgoing along the spin axis, a minimum step outside the horizon
+            let bound = divergence_momentum(&st);
+            let e = FRAC_1_SQRT_2;
+            let p = Vec4::new(-e, 0.0, 0.0, (bound * bound - e * e).sqrt());
+            let x = Vec4::new(0.0, 0.0, 0.0, st.horizon + DT_MIN);
+
+            let h = hamiltonian(&st, p, x);
+            assert!(h.abs() < 1e-4 * bound * bound, "{spin} {charge}: {h}");
+        }
+    }
+
+    #[test]
+    fn leapfrog_is_time_reversible() {
+        let st = Spacetime {
+            mass: 1.0,
+            spin: 0.9,
+            charge: 0.0,
+            lensing_radius: 120.0,
+            metric: Metric::Kerr as u32,
+            ..Spacetime::zeroed()
+        };
+        let integration = Integration {
+            method: Integrator::Leapfrog as u32,
+            ..Integration::zeroed()
+        };
+        let mut geodesic = Geodesic::new(&st, &integration, false);
+
+        let x = Vec4::new(0.0, 0.0, -12.0, 1.0);
+        let start = Phase {
+            p: null_momentum(&st, Vec3::new(0.3, 1.0, 0.0).normalize(), x.yzw()),
+            x,
+        };
+
+        let mut s = start;
+        for h in [0.05; 200].into_iter().chain([-0.05; 200]) {
+            s = geodesic.integrate(s, h);
+        }
+
+        assert!((s.x - start.x).abs().max_element() < 1e-3, "{} != {}", s.x, start.x);
+        assert!((s.p - start.p).abs().max_element() < 1e-3, "{} != {}", s.p, start.p);
+        assert!(hamiltonian(&st, s.p, s.x).abs() < 1e-3);
+    }
+
+    #[test]
+    fn analytic_gradient_matches_finite_difference() {
+        let metrics = [
+            (Metric::Schwarzschild, 0.0, 0.0),
+            (Metric::ReissnerNordstrom, 0.0, 0.4),
+            (Metric::Kerr, 0.7, 0.0),
+            (Metric::KerrNewman, 0.3, 0.2),
+        ];
+
+        for (metric, spin, charge) in metrics {
+            let st = Spacetime {
+                mass: 1.0,
+                spin,
+                charge,
+                lensing_radius: 120.0,
+                metric: metric as u32,
+                ..Spacetime::zeroed()
+            };
+
+            for i in 0..8 {
+                for j in 0..8 {
+                    for k in 0..8 {
+                        let p = Vec3::new(-7.0 + 2.0 * i as f32, -7.0 + 2.0 * j as f32, -7.0 + 2.0 * k as f32);
+                        let x = p.extend(0.0).wxyz();
+                        let dxdt = Vec4::new(1.0, -p.y, p.x, 0.5).normalize();
+
+                        let analytic = gradient_analytic(&st, dxdt, x);
+                        let finite_difference = gradient_finite_difference(&st, dxdt, x);
+
+                        assert!(
+                            (analytic - finite_difference).abs().max_element() < 0.01 * (1.0 + analytic.length()),
+                            "{metric:?} at {p}: analytic {analytic} != finite difference {finite_difference}",
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn disc_velocity_follows_circular_and_plunging_orbits() {
+        for black_hole in [
+            BlackHole::new(1.0, 0.0, 0.0),
+            BlackHole::new(1.0, 0.7, 0.0),
+            BlackHole::new(1.0, 0.3, 0.4),
+        ] {
+            let st = Spacetime {
+                mass: black_hole.mass,
+                spin: black_hole.spin,
+                charge: black_hole.charge,
+                lensing_radius: 1e5,
+                metric: Metric::KerrNewman as u32,
+                ..Spacetime::zeroed()
+            };
+
+            let isco = black_hole.isco().unwrap();
+            let (isco_energy, isco_angular_momentum) = black_hole.circular_orbit(isco).unwrap();
+            let disc = Disc {
+                isco,
+                isco_energy,
+                isco_angular_momentum,
+                ..Disc::zeroed()
+            };
+
+            for r in [isco - 0.5, isco + 0.5, 8.0, 12.0] {
+                // Point in the equatorial plane at Boyer-Lindquist radius r
+                let x = Vec4::new(0.0, r, black_hole.spin, 0.0);
+                let g = metric(&st, x);
+                let u = disc_velocity(&st, &disc, x);
+
+                let (energy, angular_momentum) = black_hole.circular_orbit(r.max(isco)).unwrap();
+                let u_t = (g * u).dot(Vec4::X);
+                let u_phi = (g * u).dot(Vec4::new(0.0, -x.z, x.y, 0.0));
+
+                assert!(
+                    ((g * u).dot(u) + 1.0).abs() < 1e-3,
+                    "{black_hole:?} at {r}: u is not normalized"
+                );
+                assert!(
+                    (u_t + energy).abs() < 1e-3,
+                    "{black_hole:?} at {r}: {} != {energy}",
+                    -u_t
+                );
+                assert!(
+                    (u_phi - angular_momentum).abs() < 1e-3 * angular_momentum,
+                    "{black_hole:?} at {r}: {u_phi} != {angular_momentum}",
+                );
+            }
+        }
+    }
+}
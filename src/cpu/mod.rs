@@ -0,0 +1,548 @@
+//! Reference implementation of the pathtracer in `pathtrace.wgsl`, used when no GPU adapter is available and as
+//! ground truth for tests.
+
+use std::thread;
+
+use glam::{Mat4, Vec2, Vec3, Vec4, Vec4Swizzles};
+use image::Rgba32FImage;
+
+use crate::diagnostics::Termination;
+use crate::disc::{DiscModel, Redshift};
+use crate::sky;
+use crate::types::Uniforms;
+
+use geodesic::{Geodesic, Phase};
+use volume::Rng;
+
+pub mod disc;
+pub mod geodesic;
+pub mod procedural;
+pub mod volume;
+
+// Max ray bounces (only applies to volumetric accretion disc)
+const MAX_BOUNCES: u32 = 4;
+// Number of timesteps for spacetime pathtracer
+pub(crate) const STEPS: u32 = 2048;
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+fn sphere_intersect(ro: Vec3, rd: Vec3, sphere: Vec4) -> f32 {
+    let oc = ro - sphere.xyz();
+    let b = oc.dot(rd);
+    let c = oc.dot(oc) - sphere.w * sphere.w;
+    let h = b * b - c;
+    if h < 0.0 {
+        return 1e10;
+    }
+
+    -b - h.sqrt()
+}
+
+fn reflect(i: Vec3, n: Vec3) -> Vec3 {
+    i - 2.0 * n.dot(i) * n
+}
+
+/// Mipmapped cubemap sampled with trilinear filtering, matching the layout of the GPU sky texture.
+pub struct CubeMap {
+    /// Faces of every mip level, starting with the full resolution.
+    levels: Vec<[Rgba32FImage; 6]>,
+}
+
+impl CubeMap {
+    /// Creates a cubemap from faces in layer order: right, left, top, bottom, front, back.
+    pub fn new(faces: [Rgba32FImage; 6]) -> Self {
+        let mut chains = faces.map(|face| sky::mip_chain(&face).into_iter());
+        let levels = (0..chains[0].len())
+            .map(|_| std::array::from_fn(|face| chains[face].next().unwrap()))
+            .collect();
+
+        Self { levels }
+    }
+
+    /// Width of the faces at full resolution.
+    pub fn size(&self) -> u32 {
+        self.levels[0][0].width()
+    }
+
+    /// Samples with linear interpolation between the two mip levels around `lod`.
+    pub fn sample_level(&self, dir: Vec3, lod: f32) -> Vec3 {
+        let lod = lod.clamp(0.0, (self.levels.len() - 1) as f32);
+        let level = lod.floor();
+        let color = self.sample_bilinear(dir, level as usize);
+
+        if lod > level {
+            color.lerp(self.sample_bilinear(dir, level as usize + 1), lod - level)
+        } else {
+            color
+        }
+    }
+
+    fn sample_bilinear(&self, dir: Vec3, level: usize) -> Vec3 {
+        let a = dir.abs();
+
+        let (face, sc, tc, ma) = if a.x >= a.y && a.x >= a.z {
+            if dir.x > 0.0 {
+                (0, -dir.z, -dir.y, a.x)
+            } else {
+                (1, dir.z, -dir.y, a.x)
+            }
+        } else if a.y >= a.z {
+            if dir.y > 0.0 {
+                (2, dir.x, dir.z, a.y)
+            } else {
+                (3, dir.x, -dir.z, a.y)
+            }
+        } else if dir.z > 0.0 {
+            (4, dir.x, -dir.y, a.z)
+        } else {
+            (5, -dir.x, -dir.y, a.z)
+        };
+
+        let face = &self.levels[level][face];
+        let (w, h) = face.dimensions();
+
+        let u = 0.5 * (sc / ma + 1.0) * w as f32 - 0.5;
+        let v = 0.5 * (tc / ma + 1.0) * h as f32 - 0.5;
+
+        let texel = |x: f32, y: f32| {
+            let p = face.get_pixel(x.clamp(0.0, (w - 1) as f32) as u32, y.clamp(0.0, (h - 1) as f32) as u32);
+            Vec3::new(p[0], p[1], p[2])
+        };
+
+        let (x0, y0) = (u.floor(), v.floor());
+        let (fx, fy) = (u - x0, v - y0);
+
+        let top = texel(x0, y0).lerp(texel(x0 + 1.0, y0), fx);
+        let bottom = texel(x0, y0 + 1.0).lerp(texel(x0 + 1.0, y0 + 1.0), fx);
+        top.lerp(bottom, fy)
+    }
+}
+
+/// Result of tracing a single sample.
+pub struct Trace {
+    /// Radiance carried by the photon, with alpha set to one unless the sample was discarded.
+    pub color: Vec4,
+    /// Maximum drift of the Hamiltonian from its initial value (only tracked for diagnostics).
+    pub hamiltonian_drift: f32,
+    pub steps: u32,
+    pub termination: Termination,
+    /// Direction in the frame of the sky texture and weight of the sky radiance added by [`sky_radiance`], if any.
+    pub sky_dir: Vec3,
+    pub sky_weight: Vec3,
+    pub samples_sky: bool,
+}
+
+/// Traces one sample of the pixel at `frag_coord`, measured in pixels from the bottom left corner of the image.
+///
+/// The radiance of the sky is not included in the color, see [`sky_radiance`].
+pub fn trace(uniforms: &Uniforms, frag_coord: Vec2, diagnostics: bool) -> Trace {
+    let view = &uniforms.view;
+    let spacetime = &uniforms.spacetime;
+
+    let render_skybox = (view.flags & 1) != 0;
+    let render_disc = ((view.flags >> 1) & 1) != 0;
+    let finite_difference = ((view.flags >> 2) & 1) != 0;
+
+    let resolution = Vec2::new(view.resolution[0] as f32, view.resolution[1] as f32);
+
+    let mut rng = Rng(Rng::triple32(view.seed)
+        ^ view
+            .frame_count
+            .wrapping_mul(view.resolution[0])
+            .wrapping_mul(view.resolution[1])
+            .wrapping_add((frag_coord.y as u32).wrapping_mul(view.resolution[0]))
+            .wrapping_add(frag_coord.x as u32));
+    let pos = (2.0 * (frag_coord + rng.rand2() - 0.5) - resolution) / resolution.y;
+
+    let camera = Mat4::from_cols_array(&view.camera);
+    let rd = (camera * pos.extend(view.focal_length).extend(1.0).normalize())
+        .xyz()
+        .normalize();
+    let mut ro = Vec3::from_array(view.position);
+
+    let t0 = sphere_intersect(ro, rd, Vec3::ZERO.extend(spacetime.lensing_radius));
+    if t0 > 0.0 && t0 < 1e10 {
+        ro += rd * t0;
+    }
+
+    let mut x = ro.extend(0.0).wxyz();
+    let mut p = geodesic::null_momentum(spacetime, rd, ro).normalize();
+
+    // Energy of the photon measured by the camera, which is at rest in the Kerr-Schild coordinates
+    let e_obs = p.dot(geodesic::static_velocity(spacetime, x));
+
+    let mut geodesic = Geodesic::new(spacetime, &uniforms.integration, finite_difference);
+
+    let mut out = Trace {
+        color: Vec4::ZERO,
+        hamiltonian_drift: 0.0,
+        steps: STEPS,
+        termination: Termination::StepBudget,
+        sky_dir: Vec3::ZERO,
+        sky_weight: Vec3::ZERO,
+        samples_sky: false,
+    };
+
+    let mut h0 = 0.0;
+    if diagnostics {
+        h0 = geodesic::hamiltonian(spacetime, p, x);
+    }
+
+    let thin_disc = render_disc && uniforms.disc.model == DiscModel::Thin as u32;
+
+    let mut discard_sample = false;
+    let mut hit_disc = false;
+    let mut bounces = 0;
+
+    let mut r = Vec3::ZERO;
+    let mut att = Vec3::ONE;
+
+    for i in 0..STEPS {
+        let mut volume = None;
+        if render_disc && !thin_disc {
+            if bounces > MAX_BOUNCES {
+                discard_sample = true;
+                out.steps = i;
+                out.termination = Termination::BounceLimit;
+                break;
+            }
+
+            // The redshift needs the metric, so it is only computed where the disc can emit
+            let mut g = 1.0;
+            if uniforms.disc.redshift != Redshift::Off as u32 && volume::inside_volume(&uniforms.disc, x.yzw()) {
+                g = geodesic::redshift(spacetime, &uniforms.disc, p, x, e_obs);
+            }
+
+            volume = Some(volume::sample_volume(&mut rng, &uniforms.disc, x.yzw(), g));
+        }
+
+        let dt1 = (1.0 / p.length()).clamp(0.1, 4.0);
+        let h = dt1 * geodesic.dt * geodesic.step_scale;
+        let state = geodesic.integrate(Phase { p, x }, h);
+
+        // The thin disc is opaque, so rays end where they cross it
+        if thin_disc {
+            if let Some(t) = disc::thin_disc_crossing(spacetime, &uniforms.disc, x, state.x) {
+                let (p, x) = (p.lerp(state.p, t), x.lerp(state.x, t));
+                r += att * disc::thin_disc_emission(spacetime, &uniforms.disc, p, x, e_obs);
+                hit_disc = true;
+                out.steps = i + 1;
+                out.termination = Termination::Disc;
+                break;
+            }
+        }
+
+        p = state.p;
+        x = state.x;
+
+        // Emission and absorption along the step that was actually taken, scattering at its end
+        if let Some(d) = volume {
+            let step_length = geodesic.accepted_step / dt1;
+            r += att * d.e * step_length;
+
+            if d.v > 0.0 {
+                let absorb = (-step_length * d.v).exp();
+
+                if absorb < rng.rand() {
+                    let v = p.yzw().length() * reflect(p.yzw().normalize(), rng.udir3());
+                    p = v.extend(p.x).wxyz();
+                    att *= d.c;
+                    bounces += 1;
+
+                    // Scattering changes the momentum, so the drift is measured from the new value
+                    if diagnostics {
+                        h0 = geodesic::hamiltonian(spacetime, p, x);
+                    }
+                }
+            }
+        }
+
+        if diagnostics {
+            out.hamiltonian_drift = out
+                .hamiltonian_drift
+                .max((geodesic::hamiltonian(spacetime, p, x) - h0).abs());
+        }
+
+        if let Some(termination) = geodesic.update_dt(p, x) {
+            out.steps = i + 1;
+            out.termination = termination;
+            break;
+        }
+    }
+
+    let dxdt = geodesic::dxdt_from_momentum(spacetime, p, x);
+    let out_dir = dxdt.yzw().normalize();
+
+    let mut col = Vec3::ZERO;
+
+    if (geodesic::radius(spacetime, x.yzw()) > spacetime.photon_orbit || hit_disc) && !discard_sample {
+        if render_skybox && !hit_disc {
+            out.sky_dir = Mat4::from_cols_array(&view.sky_rotation).transform_vector3(out_dir);
+            out.sky_weight = att * view.sky_intensity;
+            out.samples_sky = true;
+        }
+        col = r;
+    }
+
+    if !discard_sample {
+        out.color = col.extend(1.0);
+    }
+
+    out
+}
+
+/// Radiance of the sky seen by the trace of the pixel at `column` and `row` of `traces`, which holds the traces of
+/// a whole frame with `width` pixels per row.
+///
+/// With footprint filtering the derivatives of the escape directions are taken across the same 2×2 pixel quads as
+/// on the GPU.
+pub fn sky_radiance(
+    uniforms: &Uniforms,
+    sky: &CubeMap,
+    traces: &[Trace],
+    width: usize,
+    column: usize,
+    row: usize,
+) -> Vec3 {
+    let t = &traces[row * width + column];
+    if !t.samples_sky {
+        return Vec3::ZERO;
+    }
+
+    if (uniforms.view.flags & 0b10000) != 0 {
+        return t.sky_weight * procedural::procedural_sky(&uniforms.view, t.sky_dir);
+    }
+
+    let mut lod = 0.0;
+    if (uniforms.view.flags & 0b1000) != 0 {
+        let height = traces.len() / width;
+        let derivative = |column: usize, row: usize| match traces.get(row * width + column) {
+            Some(other) if column < width && row < height && other.samples_sky => (other.sky_dir - t.sky_dir).length(),
+            _ => 0.0,
+        };
+
+        let footprint = derivative(column ^ 1, row).max(derivative(column, row ^ 1));
+        lod = sky_lod(footprint, sky.size());
+    }
+
+    t.sky_weight * sky.sample_level(t.sky_dir, lod)
+}
+
+/// Mip level at which a texel of a cubemap face of `size` texels covers an angle of `footprint` radians, using the
+/// texel size at the centre of the face.
+fn sky_lod(footprint: f32, size: u32) -> f32 {
+    (0.5 * footprint * size as f32).log2().max(0.0)
+}
+
+/// Multithreaded CPU backend accumulating frames into the same layout as the GPU render target.
+pub struct Tracer {
+    width: u32,
+    height: u32,
+    sky: CubeMap,
+    accumulation: Vec<Vec4>,
+    diagnostics: Vec<Vec4>,
+}
+
+impl Tracer {
+    pub fn new(width: u32, height: u32, sky: CubeMap) -> Self {
+        let pixels = (width * height) as usize;
+
+        Self {
+            width,
+            height,
+            sky,
+            accumulation: vec![Vec4::ZERO; pixels],
+            diagnostics: vec![Vec4::ZERO; pixels],
+        }
+    }
+
+    pub fn set_sky(&mut self, sky: CubeMap) {
+        self.sky = sky;
+    }
+
+    /// Traces one sample per pixel and adds it to the accumulated image, restarting the accumulation if this is
+    /// the first frame.
+    pub fn render_frame(&mut self, uniforms: &Uniforms, diagnostics: bool) {
+        if uniforms.view.frame_count == 0 {
+            self.accumulation.fill(Vec4::ZERO);
+            self.diagnostics.fill(Vec4::ZERO);
+        }
+
+        let (width, height) = (self.width as usize, self.height as usize);
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        let rows_per_thread = height.div_ceil(threads).max(1);
+        let sky = &self.sky;
+
+        // The sky is sampled in a second pass, once the escape directions of all neighbouring pixels are known
+        let traces: Vec<Trace> = thread::scope(|scope| {
+            let handles: Vec<_> = (0..height)
+                .step_by(rows_per_thread)
+                .map(|start| {
+                    scope.spawn(move || {
+                        let end = (start + rows_per_thread).min(height);
+                        (start * width..end * width)
+                            .map(|i| {
+                                let (row, column) = (i / width, i % width);
+
+                                // Texture rows are stored top to bottom, while fragment coordinates start at the
+                                // bottom
+                                let frag_coord = Vec2::new(column as f32 + 0.5, (height - row) as f32 - 0.5);
+                                trace(uniforms, frag_coord, diagnostics)
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+
+            handles.into_iter().flat_map(|handle| handle.join().unwrap()).collect()
+        });
+        let traces = &traces;
+
+        thread::scope(|scope| {
+            let chunks = self
+                .accumulation
+                .chunks_mut(rows_per_thread * width)
+                .zip(self.diagnostics.chunks_mut(rows_per_thread * width));
+
+            for (chunk, (accumulation, diagnostic)) in chunks.enumerate() {
+                scope.spawn(move || {
+                    for (i, (color, diag)) in accumulation.iter_mut().zip(diagnostic.iter_mut()).enumerate() {
+                        let row = chunk * rows_per_thread + i / width;
+                        let column = i % width;
+                        let t = &traces[row * width + column];
+
+                        *color += t.color + sky_radiance(uniforms, sky, traces, width, column, row).extend(0.0);
+
+                        if diagnostics {
+                            *diag = Vec4::new(
+                                diag.x.max(t.hamiltonian_drift),
+                                diag.y + t.steps as f32,
+                                t.termination as u32 as f32,
+                                diag.w + 1.0,
+                            );
+                        }
+                    }
+                });
+            }
+        });
+    }
+
+    /// Accumulated image as native-endian `f32` bytes, laid out like the `Rgba32Float` GPU render target.
+    pub fn target(&self) -> Vec<u8> {
+        bytemuck::cast_slice(&self.accumulation).to_vec()
+    }
+
+    /// Accumulated diagnostics as native-endian `f32` bytes, laid out like the GPU diagnostics render target.
+    pub fn diagnostics(&self) -> Vec<u8> {
+        bytemuck::cast_slice(&self.diagnostics).to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytemuck::Zeroable;
+    use glam::{Mat4, Vec2, Vec3};
+    use image::{Rgba, Rgba32FImage};
+
+    use super::{geodesic, trace, CubeMap};
+    use crate::black_hole::BlackHole;
+    use crate::diagnostics::Termination;
+    use crate::disc::{self, DiscModel};
+    use crate::integrator::Integrator;
+    use crate::metric::Metric;
+    use crate::types::{Disc, Integration, Spacetime, Uniforms, View};
+
+    fn uniforms(integrator: Integrator) -> Uniforms {
+        let mut uniforms = Uniforms {
+            view: View {
+                camera: Mat4::IDENTITY.to_cols_array(),
+                position: [0.0, 0.0, -20.0],
+                focal_length: 1.5,
+                resolution: [64, 36],
+                ..View::zeroed()
+            },
+            spacetime: Spacetime {
+                mass: 1.0,
+                lensing_radius: 30.0,
+                metric: Metric::Schwarzschild as u32,
+                horizon: 2.0,
+                ..Spacetime::zeroed()
+            },
+            integration: Integration {
+                method: integrator as u32,
+                tolerance: 1e-4,
+                ..Integration::zeroed()
+            },
+            disc: Disc::zeroed(),
+        };
+        uniforms.spacetime.max_momentum = geodesic::divergence_momentum(&uniforms.spacetime);
+        uniforms
+    }
+
+    #[test]
+    fn cubemap_blends_mip_levels() {
+        // Vertical stripes, which average to 0.5 from the first mip level on
+        let face = Rgba32FImage::from_fn(4, 4, |x, _| Rgba([(x % 2) as f32, 0.0, 0.0, 1.0]));
+        let sky = CubeMap::new(std::array::from_fn(|_| face.clone()));
+
+        // Through the centre of the second column of the +x face
+        let dir = Vec3::new(1.0, 0.0, 0.25);
+        assert_eq!(sky.sample_level(dir, 0.0).x, 1.0);
+        assert_eq!(sky.sample_level(dir, 0.5).x, 0.75);
+        assert_eq!(sky.sample_level(dir, 1.0).x, 0.5);
+        assert_eq!(sky.sample_level(dir, 10.0).x, 0.5);
+    }
+
+    #[test]
+    fn central_ray_is_captured_and_edge_ray_escapes() {
+        let uniforms = uniforms(Integrator::Euler);
+
+        let center = trace(&uniforms, Vec2::new(32.5, 18.5), false);
+        assert_eq!(center.termination, Termination::Captured);
+
+        let edge = trace(&uniforms, Vec2::new(0.5, 18.5), false);
+        assert_eq!(edge.termination, Termination::Escaped);
+    }
+
+    #[test]
+    fn higher_order_integrators_reduce_hamiltonian_drift() {
+        // A ray passing close to the photon sphere, where integration error is largest
+        let frag_coord = Vec2::new(38.5, 18.5);
+
+        let euler = trace(&uniforms(Integrator::Euler), frag_coord, true);
+        for integrator in [Integrator::Rk4, Integrator::DormandPrince, Integrator::Leapfrog] {
+            let out = trace(&uniforms(integrator), frag_coord, true);
+            assert!(
+                out.hamiltonian_drift < euler.hamiltonian_drift,
+                "{integrator:?}: {} >= {}",
+                out.hamiltonian_drift,
+                euler.hamiltonian_drift
+            );
+        }
+    }
+
+    #[test]
+    fn thin_disc_is_opaque() {
+        let black_hole = BlackHole::new(1.0, 0.0, 0.0);
+        let isco = black_hole.isco().unwrap();
+
+        let mut uniforms = uniforms(Integrator::Rk4);
+        uniforms.view.flags = 0b10;
+        uniforms.disc = Disc {
+            isco,
+            model: DiscModel::Thin as u32,
+            outer_radius: disc::DISC_RADIUS,
+            temperature: 8000.0,
+            flux: disc::novikov_thorne_flux(&black_hole, isco, disc::DISC_RADIUS),
+            ..Disc::zeroed()
+        };
+
+        // Looking down the spin axis, a ray off to the side crosses the disc plane well outside the ISCO
+        let t = trace(&uniforms, Vec2::new(48.5, 18.5), false);
+        assert_eq!(t.termination, Termination::Disc);
+        assert!(t.color.x > 0.0);
+    }
+}
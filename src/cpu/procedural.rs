@@ -0,0 +1,118 @@
+use std::f32::consts::{PI, TAU};
+
+use glam::{Vec2, Vec3};
+
+use super::smoothstep;
+use super::volume::{blackbody, fbm, xyz2rgb, Rng};
+use crate::types::View;
+
+/// Profile of the galactic band around the equator of the sky, with the bulge at its centre in `+x`.
+fn galactic_band(dir: Vec3) -> f32 {
+    (-dir.z * dir.z / 0.015).exp()
+}
+
+fn galaxy(view: &View, dir: Vec3) -> Vec3 {
+    let band = galactic_band(dir);
+    let bulge = ((dir.x - 1.0) / 0.08).exp() * band;
+    let clouds = fbm(6.0 * dir, 5);
+    let dust = smoothstep(0.45, 0.7, fbm(12.0 * dir + 3.1, 4)) * (-dir.z * dir.z / 0.002).exp();
+
+    let color = band * (0.4 + 0.9 * clouds) * Vec3::new(0.75, 0.8, 1.0) + 1.5 * bulge * Vec3::new(1.0, 0.85, 0.65);
+    view.galaxy_brightness * 0.1 * color * (1.0 - 0.8 * dust)
+}
+
+/// Uniform random number in `[0, 1]` from a hash and an index.
+fn star_random(hash: u32, i: u32) -> f32 {
+    Rng::triple32(hash.wrapping_add(i)) as f32 / u32::MAX as f32
+}
+
+/// Point stars on a grid of equal angles on the faces of a cube, with at most one star at a random position inside
+/// each cell. Fluxes follow the distribution of a uniform population of stars, `N(>F) ~ F^-1.5`, and the stars are
+/// denser in the galactic band.
+fn stars(view: &View, dir: Vec3) -> Vec3 {
+    let a = dir.abs();
+    let (face, uv) = if a.x >= a.y && a.x >= a.z {
+        (if dir.x > 0.0 { 0 } else { 1 }, Vec2::new(dir.y, dir.z) / a.x)
+    } else if a.y >= a.z {
+        (if dir.y > 0.0 { 2 } else { 3 }, Vec2::new(dir.x, dir.z) / a.y)
+    } else {
+        (if dir.z > 0.0 { 4 } else { 5 }, Vec2::new(dir.x, dir.y) / a.z)
+    };
+
+    let grid = view.star_grid as f32;
+    let e = Vec2::new(uv.x.atan(), uv.y.atan()) * (4.0 / PI);
+    let cell = (0.5 * (e + 1.0) * grid).floor().min(Vec2::splat(grid - 1.0));
+    let index = (face * view.star_grid + cell.y as u32) * view.star_grid + cell.x as u32;
+    let hash = Rng::triple32(view.star_seed ^ Rng::triple32(index));
+
+    // Stars stay clear of the cell edges, so that only the cell of the sample needs to be checked
+    let margin = (4.0 * view.star_size / (0.5 * PI / grid)).min(0.5);
+    let offset = Vec2::splat(margin) + (1.0 - 2.0 * margin) * Vec2::new(star_random(hash, 0), star_random(hash, 1));
+    let t = (2.0 * (cell + offset) / grid - 1.0) * (PI / 4.0);
+    let star_uv = Vec2::new(t.x.tan(), t.y.tan());
+
+    let s = if face % 2 == 0 { 1.0 } else { -1.0 };
+    let star = match face / 2 {
+        0 => Vec3::new(s, star_uv.x, star_uv.y),
+        1 => Vec3::new(star_uv.x, s, star_uv.y),
+        _ => Vec3::new(star_uv.x, star_uv.y, s),
+    }
+    .normalize();
+
+    if star_random(hash, 2) >= view.star_probability * (1.0 + 2.0 * galactic_band(star)) {
+        return Vec3::ZERO;
+    }
+
+    let flux = view.star_flux * star_random(hash, 3).max(1e-3).powf(-2.0 / 3.0);
+    let temperature = 2500.0 * 12.0f32.powf(star_random(hash, 4).powf(1.5));
+    let color = xyz2rgb(blackbody(temperature));
+
+    let d = dir - star;
+    let variance = view.star_size * view.star_size;
+    let profile = (-0.5 * d.dot(d) / variance).exp() / (TAU * variance);
+    flux * profile * color / color.max_element()
+}
+
+/// Radiance of a procedural sky of stars and a galactic band in direction `dir`.
+pub fn procedural_sky(view: &View, dir: Vec3) -> Vec3 {
+    stars(view, dir) + galaxy(view, dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use bytemuck::Zeroable;
+    use glam::Vec3;
+
+    use super::{procedural_sky, stars};
+    use crate::types::View;
+
+    fn view(seed: u32, probability: f32) -> View {
+        View {
+            star_seed: seed,
+            star_grid: 64,
+            star_probability: probability,
+            star_flux: 1.0,
+            star_size: 0.002,
+            ..View::zeroed()
+        }
+    }
+
+    #[test]
+    fn stars_are_placed_by_seed() {
+        let directions: Vec<_> = (0..20000)
+            .map(|i| {
+                let t = i as f32 * 0.618_034;
+                Vec3::new(t.sin(), (0.37 * t).cos(), (1.7 * t).sin()).normalize()
+            })
+            .collect();
+        let lit = |view: &View| directions.iter().map(|&d| stars(view, d)).collect::<Vec<_>>();
+
+        let a = lit(&view(1, 1.0));
+        assert_eq!(a, lit(&view(1, 1.0)));
+        assert_ne!(a, lit(&view(2, 1.0)));
+        assert!(a.iter().any(|c| c.max_element() > 0.0));
+
+        assert!(lit(&view(1, 0.0)).iter().all(|&c| c == Vec3::ZERO));
+        assert_eq!(procedural_sky(&view(1, 0.0), Vec3::X), Vec3::ZERO);
+    }
+}
@@ -0,0 +1,207 @@
+use glam::{Mat3, Vec2, Vec3, Vec4, Vec4Swizzles};
+
+use super::smoothstep;
+use crate::disc::Redshift;
+use crate::types::Disc;
+
+const TAU: f32 = 6.283_185_5;
+
+// Height of the accretion disc
+const DISC_HEIGHT: f32 = 0.8;
+// Falloff of the volumetric accretion disc (radial, vertical)
+const DISC_FALLOFF: Vec2 = Vec2::new(0.1, 0.5);
+// Falloff of the emission of the volumetric accretion disc (radial, vertical)
+const DISC_EMISSION_FALLOFF: Vec2 = Vec2::new(0.06, 0.6);
+// Disc temperature variance
+const DISC_TEMPERATURE_SCALE: f32 = 4000.0;
+// Disc temperature base value
+const DISC_TEMPERATURE_OFFSET: f32 = 2000.0;
+// Scale of noise on the accretion disc
+const DISC_RADIAL_SCALE: f32 = 8.0;
+// Range of temperatures over which the black-body approximation is accurate
+const BLACKBODY_RANGE: Vec2 = Vec2::new(1000.0, 15000.0);
+
+/// Hash-based random number generator matching the one in the shader.
+pub struct Rng(pub u32);
+
+impl Rng {
+    pub fn triple32(v: u32) -> u32 {
+        let mut x = v;
+        x ^= x >> 17;
+        x = x.wrapping_mul(0xED5AD4BB);
+        x ^= x >> 11;
+        x = x.wrapping_mul(0xAC4C1B51);
+        x ^= x >> 15;
+        x = x.wrapping_mul(0x31848BAB);
+        x ^= x >> 14;
+        x
+    }
+
+    pub fn rand(&mut self) -> f32 {
+        self.0 = Self::triple32(self.0);
+        self.0 as f32 / u32::MAX as f32
+    }
+
+    pub fn rand2(&mut self) -> Vec2 {
+        let x = self.rand();
+        let y = self.rand();
+        Vec2::new(x, y)
+    }
+
+    /// Uniformly distributed direction on the unit sphere.
+    pub fn udir3(&mut self) -> Vec3 {
+        let z = self.rand2();
+        let r = Vec2::new(TAU * z.x, (2.0 * z.y - 1.0).acos());
+        let (s, c) = (Vec2::new(r.x.sin(), r.y.sin()), Vec2::new(r.x.cos(), r.y.cos()));
+        Vec3::new(c.x * s.y, s.x * s.y, c.y)
+    }
+}
+
+fn rotate2(v: Vec2, t: f32) -> Vec2 {
+    let (s, c) = t.sin_cos();
+    Vec2::new(v.x * c - v.y * s, v.x * s + v.y * c)
+}
+
+fn hash44(p4: Vec4) -> Vec4 {
+    let mut p = (p4 * Vec4::new(0.1031, 0.1030, 0.0973, 0.1099)).fract_gl();
+    p += p.dot(p.wzxy() + 33.33);
+    ((p.xxyz() + p.yzzw()) * p.zywx()).fract_gl()
+}
+
+fn mix(a: f32, b: f32, t: f32) -> f32 {
+    a * (1.0 - t) + b * t
+}
+
+fn noise(p: Vec3, octave: u32) -> f32 {
+    let f = p.fract_gl();
+    let i = p.floor();
+
+    let s = Vec3::new(
+        smoothstep(0.0, 1.0, f.x),
+        smoothstep(0.0, 1.0, f.y),
+        smoothstep(0.0, 1.0, f.z),
+    );
+
+    let t = |o: Vec3| hash44((i + o).extend(octave as f32)).x;
+
+    let t0 = t(Vec3::new(0.0, 0.0, 0.0));
+    let t1 = t(Vec3::new(1.0, 0.0, 0.0));
+    let t2 = t(Vec3::new(0.0, 1.0, 0.0));
+    let t3 = t(Vec3::new(1.0, 1.0, 0.0));
+    let t4 = t(Vec3::new(0.0, 0.0, 1.0));
+    let t5 = t(Vec3::new(1.0, 0.0, 1.0));
+    let t6 = t(Vec3::new(0.0, 1.0, 1.0));
+    let t7 = t(Vec3::new(1.0, 1.0, 1.0));
+
+    mix(
+        mix(mix(t0, t1, s.x), mix(t2, t3, s.x), s.y),
+        mix(mix(t4, t5, s.x), mix(t6, t7, s.x), f.y),
+        s.z,
+    )
+}
+
+pub fn fbm(p: Vec3, iter: u32) -> f32 {
+    let mut v = 0.0;
+    let mut acc = 0.0;
+    let mut att = 0.5;
+    let mut scale = 1.0;
+
+    for _ in 0..iter {
+        v += att * noise(scale * p, iter);
+        acc += att;
+        att *= 0.5;
+        scale *= 2.5;
+    }
+
+    if acc != 0.0 {
+        v / acc
+    } else {
+        v
+    }
+}
+
+const XYZ_TO_SRGB: Mat3 = Mat3::from_cols_array(&[3.240, -1.537, -0.499, -0.969, 1.876, 0.042, 0.056, -0.204, 1.057]);
+
+pub fn xyz2rgb(xyz: Vec3) -> Vec3 {
+    XYZ_TO_SRGB.transpose() * xyz
+}
+
+/// Computes the XYZ color of an ideal black-body radiator, given its temperature in Kelvin.
+#[allow(clippy::excessive_precision)]
+pub fn blackbody(t: f32) -> Vec3 {
+    let u =
+        (0.860117757 + 1.54118254E-4 * t + 1.28641212E-7 * t * t) / (1.0 + 8.42420235E-4 * t + 7.08145163E-7 * t * t);
+    let v =
+        (0.317398726 + 4.22806245E-5 * t + 4.20481691E-8 * t * t) / (1.0 - 2.89741816E-5 * t + 1.61456053E-7 * t * t);
+
+    let xyy = Vec2::new(3.0 * u, 2.0 * v) / (2.0 * u - 8.0 * v + 4.0);
+    Vec3::new(xyy.x / xyy.y, 1.0, (1.0 - xyy.x - xyy.y) / xyy.y)
+}
+
+/// Color of black-body radiation emitted at the given temperature as seen by the camera, normalized to its brightest
+/// channel before the intensity is scaled with the redshift factor. Colors outside the sRGB gamut keep their negative
+/// components, so that wider output color spaces can show them.
+pub fn emission(disc: &Disc, temperature: f32, redshift: f32) -> Vec3 {
+    let e = xyz2rgb(blackbody(
+        (redshift * temperature).clamp(BLACKBODY_RANGE.x, BLACKBODY_RANGE.y),
+    ));
+    let e = e / e.max_element().max(0.01);
+
+    // I_ν / ν³ is invariant along the ray, so the specific intensity scales with g³ and the bolometric one with g⁴
+    if disc.redshift == Redshift::Specific as u32 {
+        e * redshift.powi(3)
+    } else if disc.redshift == Redshift::Bolometric as u32 {
+        e * redshift.powi(4)
+    } else {
+        e
+    }
+}
+
+pub struct SampleVolumeOut {
+    pub c: Vec3,
+    pub e: Vec3,
+    pub v: f32,
+}
+
+/// Whether `p` lies within the bounds of the volumetric disc, outside of which it neither emits nor absorbs.
+pub fn inside_volume(disc: &Disc, p: Vec3) -> bool {
+    p.truncate().length_squared() <= disc.outer_radius * disc.outer_radius && p.z * p.z <= DISC_HEIGHT * DISC_HEIGHT
+}
+
+pub fn sample_volume(rng: &mut Rng, disc: &Disc, p: Vec3, redshift: f32) -> SampleVolumeOut {
+    let mut out = SampleVolumeOut {
+        c: Vec3::new(0.3, 0.2, 0.1),
+        e: Vec3::ZERO,
+        v: 0.0,
+    };
+
+    // Reject if not hit disc
+    if !inside_volume(disc, p) {
+        return out;
+    }
+
+    let q = rotate2(p.truncate(), (8.0 * p.z) + (DISC_RADIAL_SCALE * p.truncate().length()));
+    let n0 = fbm(DISC_RADIAL_SCALE * q.extend(p.z), 8);
+
+    let d_falloff = (Vec3::new(DISC_FALLOFF.x, DISC_FALLOFF.x, DISC_FALLOFF.y) * p).length();
+    let e_falloff = (Vec3::new(
+        DISC_EMISSION_FALLOFF.x,
+        DISC_EMISSION_FALLOFF.x,
+        DISC_EMISSION_FALLOFF.y,
+    ) * p)
+        .length();
+
+    // Sample the color temperature of the accretion disc (with some random jitter)
+    let t = rng.rand();
+    out.e = emission(
+        disc,
+        (DISC_TEMPERATURE_SCALE * t * t) + DISC_TEMPERATURE_OFFSET,
+        redshift,
+    );
+
+    // Account for density and emission falloff near edges of disc
+    out.e *= 128.0 * (n0 - e_falloff).max(0.0) / ((0.5 * p).dot(0.5 * p) + 0.05);
+    out.v = 128.0 * (n0 - d_falloff).max(0.0);
+
+    out
+}
@@ -0,0 +1,136 @@
+use image::{GrayImage, ImageBuffer, Luma, Rgb, RgbImage, Rgba32FImage};
+
+/// Reason a photon path stopped being integrated.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Termination {
+    /// Left the sphere of influence in which lensing occurs.
+    Escaped = 1,
+    /// Crossed the outer event horizon.
+    Captured = 2,
+    /// The momentum grew without bound, usually from integration error close to a singularity.
+    Diverged = 3,
+    /// Ran out of integration steps.
+    StepBudget = 4,
+    /// Scattered more often than the bounce limit of the volumetric accretion disc allows.
+    BounceLimit = 5,
+    /// Hit the optically thick thin accretion disc.
+    Disc = 6,
+}
+
+impl Termination {
+    pub fn from_u32(v: u32) -> Option<Self> {
+        match v {
+            1 => Some(Termination::Escaped),
+            2 => Some(Termination::Captured),
+            3 => Some(Termination::Diverged),
+            4 => Some(Termination::StepBudget),
+            5 => Some(Termination::BounceLimit),
+            6 => Some(Termination::Disc),
+            _ => None,
+        }
+    }
+
+    /// Colour used for this termination reason by [`Diagnostics::termination_image`].
+    pub fn color(self) -> [u8; 3] {
+        match self {
+            Termination::Escaped => [40, 90, 220],
+            Termination::Captured => [0, 0, 0],
+            Termination::Diverged => [220, 40, 40],
+            Termination::StepBudget => [230, 200, 40],
+            Termination::BounceLimit => [200, 60, 200],
+            Termination::Disc => [240, 130, 30],
+        }
+    }
+}
+
+/// Per-pixel integration diagnostics accumulated over all rendered frames, stored top row first.
+pub struct Diagnostics {
+    width: u32,
+    height: u32,
+    data: Vec<[f32; 4]>,
+}
+
+impl Diagnostics {
+    /// Builds the diagnostics from the raw contents of the diagnostics render target, which is stored bottom row
+    /// first with four floats per pixel.
+    pub fn from_raw(width: u32, height: u32, raw: &[f32]) -> Self {
+        let mut data = Vec::with_capacity((width * height) as usize);
+
+        for row in raw.chunks((width * 4) as usize).rev() {
+            data.extend(row.chunks(4).map(|p| [p[0], p[1], p[2], p[3]]));
+        }
+
+        Self { width, height, data }
+    }
+
+    /// Width and height in pixels.
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn pixel(&self, x: u32, y: u32) -> [f32; 4] {
+        self.data[(y * self.width + x) as usize]
+    }
+
+    /// Maximum drift of the Hamiltonian `g^{μν} p_μ p_ν` from its initial value over all samples of a pixel.
+    pub fn hamiltonian_drift(&self, x: u32, y: u32) -> f32 {
+        self.pixel(x, y)[0]
+    }
+
+    /// Mean number of integration steps per sample of a pixel.
+    pub fn steps(&self, x: u32, y: u32) -> f32 {
+        let p = self.pixel(x, y);
+        if p[3] > 0.0 {
+            p[1] / p[3]
+        } else {
+            0.0
+        }
+    }
+
+    /// Termination reason of the most recent sample of a pixel.
+    pub fn termination(&self, x: u32, y: u32) -> Option<Termination> {
+        Termination::from_u32(self.pixel(x, y)[2] as u32)
+    }
+
+    /// Number of samples traced for a pixel, including discarded ones.
+    pub fn samples(&self, x: u32, y: u32) -> u32 {
+        self.pixel(x, y)[3] as u32
+    }
+
+    /// Raw diagnostics with the maximum Hamiltonian drift, mean step count, termination reason and sample count in
+    /// the four channels, suitable for saving as OpenEXR.
+    pub fn raw_image(&self) -> Rgba32FImage {
+        ImageBuffer::from_fn(self.width, self.height, |x, y| {
+            image::Rgba([
+                self.hamiltonian_drift(x, y),
+                self.steps(x, y),
+                self.pixel(x, y)[2],
+                self.samples(x, y) as f32,
+            ])
+        })
+    }
+
+    /// Hamiltonian drift on a logarithmic scale, mapping `1e-8` and below to black and `1` and above to white.
+    pub fn hamiltonian_drift_image(&self) -> GrayImage {
+        ImageBuffer::from_fn(self.width, self.height, |x, y| {
+            let v = (self.hamiltonian_drift(x, y).max(1e-8).log10() + 8.0) / 8.0;
+            Luma([(v.clamp(0.0, 1.0) * 255.0) as u8])
+        })
+    }
+
+    /// Mean step count, mapping zero to black and `max_steps` to white.
+    pub fn steps_image(&self, max_steps: u32) -> GrayImage {
+        ImageBuffer::from_fn(self.width, self.height, |x, y| {
+            let v = self.steps(x, y) / max_steps as f32;
+            Luma([(v.clamp(0.0, 1.0) * 255.0) as u8])
+        })
+    }
+
+    /// Termination reasons colour coded with [`Termination::color`], with unsampled pixels in grey.
+    pub fn termination_image(&self) -> RgbImage {
+        ImageBuffer::from_fn(self.width, self.height, |x, y| {
+            Rgb(self.termination(x, y).map_or([128, 128, 128], Termination::color))
+        })
+    }
+}
@@ -0,0 +1,178 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::black_hole::BlackHole;
+use crate::error::Error;
+
+/// How the redshift factor `g = ν_obs / ν_emit` is applied to the light emitted by the accretion disc.
+///
+/// The observed temperature of the gas is always `g·T_emit`. Since `I_ν / ν³` is invariant along a ray, the
+/// intensity is additionally scaled with a power of `g` that depends on what the image is meant to show.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Redshift {
+    /// Emission is not shifted, the disc looks the same from every direction.
+    #[default]
+    Off = 0,
+    /// Specific intensity at a fixed frequency, scaled with `g³`.
+    Specific = 1,
+    /// Intensity integrated over all frequencies, scaled with `g⁴`.
+    Bolometric = 2,
+}
+
+impl FromStr for Redshift {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "off" => Ok(Redshift::Off),
+            "specific" => Ok(Redshift::Specific),
+            "bolometric" => Ok(Redshift::Bolometric),
+            _ => Err(Error::UnknownOption {
+                name: "redshift",
+                value: s.to_string(),
+            }),
+        }
+    }
+}
+
+/// Shape of the accretion disc.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DiscModel {
+    /// Thick, partially transparent disc of noisy gas that scatters light, extending to [`VOLUME_RADIUS`].
+    #[default]
+    Volumetric = 0,
+    /// Geometrically thin, optically thick disc in the equatorial plane with the Novikov-Thorne temperature
+    /// profile, extending from the ISCO to [`DISC_RADIUS`].
+    #[serde(alias = "novikov-thorne")]
+    Thin = 1,
+}
+
+impl DiscModel {
+    /// Radius at which the disc ends.
+    pub fn outer_radius(self) -> f32 {
+        match self {
+            DiscModel::Volumetric => VOLUME_RADIUS,
+            DiscModel::Thin => DISC_RADIUS,
+        }
+    }
+}
+
+impl FromStr for DiscModel {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "volumetric" => Ok(DiscModel::Volumetric),
+            "thin" | "novikov-thorne" => Ok(DiscModel::Thin),
+            _ => Err(Error::UnknownOption {
+                name: "disc model",
+                value: s.to_string(),
+            }),
+        }
+    }
+}
+
+/// Outer radius of the thin accretion disc.
+pub const DISC_RADIUS: f32 = 20.0;
+
+/// Outer radius of the volumetric accretion disc, whose density and emission fall off well before it.
+pub const VOLUME_RADIUS: f32 = 10.0;
+
+/// Number of samples of the flux profile passed to the shader.
+pub const FLUX_SAMPLES: usize = 64;
+
+/// Tabulates the Novikov-Thorne flux emitted by a thin disc at evenly spaced radii from `inner` to `outer`,
+/// normalized to a maximum of one.
+///
+/// The flux is computed from the Page-Thorne integral
+/// `F(r) ∝ -Ω'(r) / (r (E - ΩL)²) ∫ (E - ΩL) L'(r) dr` over circular orbits between `inner` and `r`. The table is
+/// all zeros if there are no stable circular orbits in that range.
+pub fn novikov_thorne_flux(black_hole: &BlackHole, inner: f32, outer: f32) -> [f32; FLUX_SAMPLES] {
+    const SUBSTEPS: usize = 16;
+
+    let mut flux = [0.0; FLUX_SAMPLES];
+    if inner <= 0.0 || inner >= outer {
+        return flux;
+    }
+
+    let orbit = |r: f32| {
+        let (energy, angular_momentum) = black_hole.circular_orbit(r)?;
+        Some((
+            energy as f64,
+            angular_momentum as f64,
+            black_hole.keplerian_angular_velocity(r) as f64,
+        ))
+    };
+    let derivative = |f: &dyn Fn(f32) -> Option<f64>, r: f32| {
+        let h = 1e-2 * r;
+        Some((f(r + h)? - f(r - h)?) / (2.0 * h as f64))
+    };
+    let angular_momentum = |r: f32| orbit(r).map(|(_, l, _)| l);
+    let angular_velocity = |r: f32| orbit(r).map(|(_, _, omega)| omega);
+
+    let dr = (outer - inner) / (FLUX_SAMPLES - 1) as f32;
+    let mut integral = 0.0;
+
+    for (i, sample) in flux.iter_mut().enumerate().skip(1) {
+        let r = inner + i as f32 * dr;
+
+        // Midpoint rule over the interval since the previous sample
+        for j in 0..SUBSTEPS {
+            let rj = r - dr + (j as f32 + 0.5) * dr / SUBSTEPS as f32;
+            let (Some((e, l, omega)), Some(dl)) = (orbit(rj), derivative(&angular_momentum, rj)) else {
+                return [0.0; FLUX_SAMPLES];
+            };
+
+            integral += (e - omega * l) * dl * (dr / SUBSTEPS as f32) as f64;
+        }
+
+        let (Some((e, l, omega)), Some(domega)) = (orbit(r), derivative(&angular_velocity, r)) else {
+            return [0.0; FLUX_SAMPLES];
+        };
+
+        *sample = (-domega / (r as f64 * (e - omega * l).powi(2)) * integral) as f32;
+    }
+
+    let max = flux.iter().copied().fold(0.0, f32::max);
+    if max > 0.0 {
+        flux.iter_mut().for_each(|f| *f /= max);
+    }
+
+    flux
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{novikov_thorne_flux, DISC_RADIUS, FLUX_SAMPLES};
+    use crate::black_hole::BlackHole;
+
+    /// Closed form of the Page-Thorne flux of a Schwarzschild black hole with unit mass, up to a constant factor.
+    fn schwarzschild_flux(r: f32) -> f32 {
+        let x = r.sqrt();
+        let x0 = 6.0f32.sqrt();
+        let s3 = 3.0f32.sqrt();
+
+        let f = x - x0 - 0.5 * s3 * ((x - s3) / (x0 - s3)).ln() + 0.5 * s3 * ((x + s3) / (x0 + s3)).ln();
+        f / (r * x * x * (x * x * x - 3.0 * x))
+    }
+
+    #[test]
+    fn flux_matches_schwarzschild_closed_form() {
+        let black_hole = BlackHole::new(1.0, 0.0, 0.0);
+        let flux = novikov_thorne_flux(&black_hole, 6.0, DISC_RADIUS);
+
+        let radii = (0..FLUX_SAMPLES).map(|i| 6.0 + i as f32 * (DISC_RADIUS - 6.0) / (FLUX_SAMPLES - 1) as f32);
+        let expected: Vec<_> = radii.map(schwarzschild_flux).collect();
+        let max = expected.iter().copied().fold(0.0, f32::max);
+
+        assert_eq!(flux[0], 0.0);
+        for (i, (f, e)) in flux.iter().zip(&expected).enumerate() {
+            assert!((f - e / max).abs() < 1e-2, "sample {i}: {f} != {}", e / max);
+        }
+    }
+}
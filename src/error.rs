@@ -0,0 +1,135 @@
+use std::fmt;
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub enum Error {
+    /// A black hole parameter is out of its valid range.
+    InvalidParameter { name: &'static str, value: f32 },
+    /// A named option does not match any of its possible values.
+    UnknownOption { name: &'static str, value: String },
+    /// The black hole parameters satisfy `a² + Q² > M²`, which has no event horizon.
+    NakedSingularity { mass: f32, spin: f32, charge: f32 },
+    /// The camera is at the point it looks at, or looks along its up direction.
+    DegenerateCamera,
+    /// An image could not be loaded or saved.
+    Image { path: PathBuf, error: image::ImageError },
+    /// Faces of a skybox are missing from its directory.
+    MissingSkyboxFaces { dir: PathBuf, faces: Vec<&'static str> },
+    /// A face of a skybox is not square or differs in size from the first face, which is `size` pixels wide.
+    InvalidSkyboxFace {
+        face: &'static str,
+        width: u32,
+        height: u32,
+        size: u32,
+    },
+    /// An equirectangular panorama has no pixels to resample into a skybox.
+    EmptyPanorama { width: u32, height: u32 },
+    /// An extra output channel needs diagnostics, which were not recorded.
+    MissingDiagnostics(&'static str),
+    /// Extra output channels were requested for a format other than OpenEXR.
+    UnsupportedChannels(PathBuf),
+    /// 16 bits per channel were requested for a format other than PNG and TIFF.
+    UnsupportedBitDepth(PathBuf),
+    /// A colour space other than sRGB was requested for a format that cannot embed an ICC profile.
+    UnsupportedColorSpace(PathBuf),
+    /// A file could not be read or written.
+    Io { path: PathBuf, error: std::io::Error },
+    /// A scene file is not valid TOML or contains unknown settings.
+    Scene { path: PathBuf, error: toml::de::Error },
+    /// No GPU adapter is available.
+    NoAdapter,
+    /// The GPU adapter could not provide a device.
+    RequestDevice(wgpu::RequestDeviceError),
+    /// The shader could not be compiled or a render pipeline could not be created.
+    Pipeline(wgpu::Error),
+    /// The GPU rejected a resource or command, or ran out of memory.
+    Gpu(wgpu::Error),
+    /// A buffer could not be mapped to read back a render.
+    BufferMapping(wgpu::BufferAsyncError),
+    /// The GPU device was lost, e.g. because the driver was reset.
+    DeviceLost(String),
+}
+
+impl Error {
+    /// Whether the error comes from an invalid setting, as opposed to a failure while loading, rendering or saving.
+    pub fn is_invalid_setting(&self) -> bool {
+        matches!(
+            self,
+            Error::InvalidParameter { .. }
+                | Error::UnknownOption { .. }
+                | Error::NakedSingularity { .. }
+                | Error::DegenerateCamera
+                | Error::MissingDiagnostics(_)
+                | Error::UnsupportedChannels(_)
+                | Error::UnsupportedBitDepth(_)
+                | Error::UnsupportedColorSpace(_)
+        )
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidParameter { name, value } => write!(f, "invalid value for {name}: {value}"),
+            Error::UnknownOption { name, value } => write!(f, "unknown {name}: {value}"),
+            Error::NakedSingularity { mass, spin, charge } => write!(
+                f,
+                "a² + Q² > M² (M = {mass}, a = {spin}, Q = {charge}) describes a naked singularity"
+            ),
+            Error::DegenerateCamera => write!(f, "the camera must not be at its target or look along its up direction"),
+            Error::Image { path, error } => write!(f, "{}: {error}", path.display()),
+            Error::MissingSkyboxFaces { dir, faces } => write!(
+                f,
+                "{}: missing skybox faces {} (expected an image named after each of {})",
+                dir.display(),
+                faces.join(", "),
+                crate::sky::FACE_NAMES.join(", ")
+            ),
+            Error::InvalidSkyboxFace {
+                face,
+                width,
+                height,
+                size,
+            } => write!(
+                f,
+                "skybox face {face} is {width}×{height} pixels, but all faces must be square and {size}×{size}"
+            ),
+            Error::EmptyPanorama { width, height } => {
+                write!(f, "the sky panorama is {width}×{height} pixels, but must not be empty")
+            }
+            Error::MissingDiagnostics(channel) => {
+                write!(
+                    f,
+                    "the {channel} channel needs the integration diagnostics to be recorded"
+                )
+            }
+            Error::UnsupportedChannels(path) => {
+                write!(f, "{}: extra channels can only be written to OpenEXR", path.display())
+            }
+            Error::UnsupportedBitDepth(path) => {
+                write!(
+                    f,
+                    "{}: 16 bits per channel can only be written to PNG or TIFF",
+                    path.display()
+                )
+            }
+            Error::UnsupportedColorSpace(path) => {
+                write!(
+                    f,
+                    "{}: colour spaces other than sRGB can only be written to PNG, TIFF or JPEG",
+                    path.display()
+                )
+            }
+            Error::Io { path, error } => write!(f, "{}: {error}", path.display()),
+            Error::Scene { path, error } => write!(f, "{}: {error}", path.display()),
+            Error::NoAdapter => write!(f, "no suitable GPU adapter found"),
+            Error::RequestDevice(error) => write!(f, "failed to create the GPU device: {error}"),
+            Error::Pipeline(error) => write!(f, "failed to create the render pipeline: {error}"),
+            Error::Gpu(error) => write!(f, "GPU error: {error}"),
+            Error::BufferMapping(error) => write!(f, "failed to read back the render: {error}"),
+            Error::DeviceLost(reason) => write!(f, "the GPU device was lost: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,209 @@
+use std::f64::consts::FRAC_PI_2;
+use std::fmt;
+
+use glam::Vec2;
+
+use crate::black_hole::BlackHole;
+
+/// Characteristic radii of a black hole, in Boyer-Lindquist coordinates.
+///
+/// Prograde and retrograde refer to orbits in the equatorial plane that co-rotate and counter-rotate with the black
+/// hole. Every radius is `None` where the corresponding surface or orbit does not exist, e.g. for naked
+/// singularities.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Geometry {
+    pub outer_horizon: Option<f32>,
+    pub inner_horizon: Option<f32>,
+    /// Outer boundary of the ergosphere in the equatorial plane, where it extends furthest.
+    pub ergosphere: Option<f32>,
+    pub isco_prograde: Option<f32>,
+    pub isco_retrograde: Option<f32>,
+    pub photon_orbit_prograde: Option<f32>,
+    pub photon_orbit_retrograde: Option<f32>,
+}
+
+impl Geometry {
+    pub fn new(black_hole: &BlackHole) -> Self {
+        // Orbits of `BlackHole` rotate in the direction of positive φ, which is prograde for positive spin
+        let prograde = BlackHole::new(black_hole.mass, black_hole.spin.abs(), black_hole.charge);
+        let retrograde = BlackHole::new(black_hole.mass, -black_hole.spin.abs(), black_hole.charge);
+
+        Self {
+            outer_horizon: black_hole.outer_horizon(),
+            inner_horizon: black_hole.inner_horizon(),
+            ergosphere: black_hole.ergosphere(FRAC_PI_2 as f32),
+            isco_prograde: prograde.isco(),
+            isco_retrograde: retrograde.isco(),
+            photon_orbit_prograde: prograde.photon_orbit(),
+            photon_orbit_retrograde: retrograde.photon_orbit(),
+        }
+    }
+}
+
+impl fmt::Display for Geometry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rows = [
+            ("outer horizon", self.outer_horizon),
+            ("inner horizon", self.inner_horizon),
+            ("ergosphere (equatorial)", self.ergosphere),
+            ("ISCO (prograde)", self.isco_prograde),
+            ("ISCO (retrograde)", self.isco_retrograde),
+            ("photon orbit (prograde)", self.photon_orbit_prograde),
+            ("photon orbit (retrograde)", self.photon_orbit_retrograde),
+        ];
+
+        for (name, radius) in rows {
+            match radius {
+                Some(r) => writeln!(f, "{name:<26}{r:.6}")?,
+                None => writeln!(f, "{name:<26}none")?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Boundary of the shadow of the black hole seen by a distant observer at `inclination` radians from the spin axis.
+///
+/// The points are celestial coordinates `(α, β)` in units of length, with `α` perpendicular to the projected spin
+/// axis and `β` along it, ordered around the curve. They are traced by the spherical photon orbits between the
+/// prograde and retrograde equatorial photon orbits (Bardeen 1973). The curve is empty for naked singularities or if
+/// these orbits do not exist.
+pub fn shadow(black_hole: &BlackHole, inclination: f32, samples: usize) -> Vec<Vec2> {
+    let (m, a, q) = (black_hole.mass as f64, black_hole.spin as f64, black_hole.charge as f64);
+
+    // The celestial coordinates are singular on the spin axis, so observers are kept slightly away from it
+    let inclination = (inclination as f64).clamp(1e-3, std::f64::consts::PI - 1e-3);
+    let (sin, cos) = inclination.sin_cos();
+
+    let retrograde = BlackHole::new(black_hole.mass, -black_hole.spin, black_hole.charge);
+    let (Some(_), Some(r0), Some(r1)) = (
+        black_hole.outer_horizon(),
+        black_hole.photon_orbit(),
+        retrograde.photon_orbit(),
+    ) else {
+        return Vec::new();
+    };
+
+    let samples = samples.max(4);
+
+    // Without spin all spherical photon orbits coincide and the shadow is a circle of the critical impact parameter
+    if a.abs() < 1e-6 {
+        let r = r0 as f64;
+        let b = (r * r * r * r / (r * r - 2.0 * m * r + q * q)).sqrt();
+
+        return (0..samples)
+            .map(|i| {
+                let t = std::f64::consts::TAU * i as f64 / samples as f64;
+                Vec2::new((b * t.cos()) as f32, (b * t.sin()) as f32)
+            })
+            .collect();
+    }
+
+    // Impact parameters ξ = L/E and η = C/E² of the spherical photon orbit at radius r
+    let impact_parameters = |r: f64| {
+        let delta = r * r - 2.0 * m * r + a * a + q * q;
+        let xi = (r * r + a * a - 2.0 * r * delta / (r - m)) / a;
+        let eta = 4.0 * r * r * delta / ((r - m) * (r - m)) - (xi - a) * (xi - a);
+        (xi, eta)
+    };
+
+    let beta2 = |r: f64| {
+        let (xi, eta) = impact_parameters(r);
+        eta + a * a * cos * cos - xi * xi * cos * cos / (sin * sin)
+    };
+
+    // Photon orbits only contribute where β² >= 0, which is a single interval between the equatorial orbits
+    let (lo, hi) = (r0.min(r1) as f64, r0.max(r1) as f64);
+    let scan = 1024;
+    let inside: Vec<f64> = (0..=scan)
+        .map(|i| lo + (hi - lo) * i as f64 / scan as f64)
+        .filter(|&r| beta2(r) >= 0.0)
+        .collect();
+    let (Some(&first), Some(&last)) = (inside.first(), inside.last()) else {
+        return Vec::new();
+    };
+
+    let refine = |mut outside: f64, mut inside: f64| {
+        for _ in 0..48 {
+            let mid = 0.5 * (outside + inside);
+            if beta2(mid) >= 0.0 {
+                inside = mid;
+            } else {
+                outside = mid;
+            }
+        }
+        inside
+    };
+    let step = (hi - lo) / scan as f64;
+    let start = refine((first - step).max(lo), first);
+    let end = refine((last + step).min(hi), last);
+
+    let half = samples / 2;
+    let mut points = Vec::with_capacity(2 * half);
+
+    for i in 0..half {
+        let r = start + (end - start) * i as f64 / (half - 1) as f64;
+        let (xi, _) = impact_parameters(r);
+        let beta = beta2(r).max(0.0).sqrt();
+        points.push(Vec2::new((-xi / sin) as f32, beta as f32));
+    }
+
+    for i in (0..half).rev() {
+        let p = points[i];
+        points.push(Vec2::new(p.x, -p.y));
+    }
+
+    points
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_2;
+
+    use super::{shadow, Geometry};
+    use crate::black_hole::BlackHole;
+
+    #[test]
+    fn radii_match_known_values() {
+        let schwarzschild = Geometry::new(&BlackHole::new(1.0, 0.0, 0.0));
+        assert_eq!(schwarzschild.outer_horizon, Some(2.0));
+        assert_eq!(schwarzschild.inner_horizon, Some(0.0));
+        assert_eq!(schwarzschild.ergosphere, Some(2.0));
+        assert!((schwarzschild.photon_orbit_prograde.unwrap() - 3.0).abs() < 1e-4);
+        assert!((schwarzschild.photon_orbit_retrograde.unwrap() - 3.0).abs() < 1e-4);
+
+        let extremal_kerr = Geometry::new(&BlackHole::new(1.0, 1.0, 0.0));
+        assert!((extremal_kerr.photon_orbit_prograde.unwrap() - 1.0).abs() < 1e-2);
+        assert!((extremal_kerr.photon_orbit_retrograde.unwrap() - 4.0).abs() < 1e-4);
+        assert!((extremal_kerr.isco_retrograde.unwrap() - 9.0).abs() < 1e-3);
+
+        let extremal_reissner_nordstrom = Geometry::new(&BlackHole::new(1.0, 0.0, 1.0));
+        assert!((extremal_reissner_nordstrom.photon_orbit_prograde.unwrap() - 2.0).abs() < 1e-4);
+
+        let naked = Geometry::new(&BlackHole::new(1.0, 0.0, 1.5));
+        assert_eq!(naked.outer_horizon, None);
+    }
+
+    #[test]
+    fn schwarzschild_shadow_is_a_circle() {
+        let curve = shadow(&BlackHole::new(1.0, 0.0, 0.0), 1.0, 64);
+        assert_eq!(curve.len(), 64);
+
+        for p in curve {
+            assert!((p.length() - 27.0f32.sqrt()).abs() < 1e-3, "{p}");
+        }
+    }
+
+    #[test]
+    fn extremal_kerr_shadow_is_flattened_on_the_prograde_side() {
+        let curve = shadow(&BlackHole::new(1.0, 0.999, 0.0), FRAC_PI_2, 256);
+
+        let min = curve.iter().map(|p| p.x).fold(f32::INFINITY, f32::min);
+        let max = curve.iter().map(|p| p.x).fold(f32::NEG_INFINITY, f32::max);
+
+        // Edge-on, the shadow of an extremal Kerr black hole spans -2M to 7M
+        assert!((min + 2.0).abs() < 0.1, "{min}");
+        assert!((max - 7.0).abs() < 0.01, "{max}");
+    }
+}
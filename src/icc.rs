@@ -0,0 +1,225 @@
+//! ICC profiles describing the colour spaces of tone-mapped images, so that viewers show their primaries and transfer
+//! function as intended.
+//!
+//! The profiles are minimal version 4 display profiles: three colorants adapted to the D50 white point of the profile
+//! connection space, and a parametric curve decoding the transfer function of each channel.
+
+use glam::{DMat3, DVec3};
+
+use crate::output::ColorSpace;
+
+/// White point of the profile connection space.
+const D50: DVec3 = DVec3::new(0.9642, 1.0, 0.8249);
+
+/// Bradford cone response matrix, stored row by row.
+const BRADFORD: DMat3 = DMat3::from_cols_array(&[
+    0.8951, 0.2664, -0.1614, -0.7502, 1.7135, 0.0367, 0.0389, -0.0685, 1.0296,
+]);
+
+/// Rec. 2020 transfer function constants, see `linear_to_rec2020` in [`crate::output`].
+const REC2020_ALPHA: f64 = 1.099_296_826_809_44;
+const REC2020_BETA: f64 = 0.018_053_968_510_807;
+
+/// Chromaticities of the red, green and blue primaries and of the white point.
+fn chromaticities(color_space: ColorSpace) -> [(f64, f64); 4] {
+    const D65: (f64, f64) = (0.3127, 0.3290);
+
+    match color_space {
+        ColorSpace::Srgb | ColorSpace::LinearSrgb => [(0.64, 0.33), (0.30, 0.60), (0.15, 0.06), D65],
+        ColorSpace::DisplayP3 => [(0.680, 0.320), (0.265, 0.690), (0.150, 0.060), D65],
+        ColorSpace::Rec2020 => [(0.708, 0.292), (0.170, 0.797), (0.131, 0.046), D65],
+    }
+}
+
+/// Parameters `g, a, b, c, d` of the parametric curve decoding the transfer function, `(a x + b)^g` from `d` on and
+/// `c x` below it.
+fn transfer(color_space: ColorSpace) -> [f64; 5] {
+    match color_space {
+        ColorSpace::Srgb | ColorSpace::DisplayP3 => [2.4, 1.0 / 1.055, 0.055 / 1.055, 1.0 / 12.92, 0.04045],
+        ColorSpace::LinearSrgb => [1.0, 1.0, 0.0, 1.0, 0.0],
+        ColorSpace::Rec2020 => [
+            1.0 / 0.45,
+            1.0 / REC2020_ALPHA,
+            (REC2020_ALPHA - 1.0) / REC2020_ALPHA,
+            1.0 / 4.5,
+            4.5 * REC2020_BETA,
+        ],
+    }
+}
+
+fn name(color_space: ColorSpace) -> &'static str {
+    match color_space {
+        ColorSpace::Srgb => "sRGB",
+        ColorSpace::LinearSrgb => "Linear sRGB",
+        ColorSpace::DisplayP3 => "Display P3",
+        ColorSpace::Rec2020 => "Rec. 2020",
+    }
+}
+
+fn s15_fixed16(v: f64) -> [u8; 4] {
+    ((v * 65536.0).round() as i32).to_be_bytes()
+}
+
+fn xyz_tag(xyz: DVec3) -> Vec<u8> {
+    let mut tag = b"XYZ \0\0\0\0".to_vec();
+    tag.extend(xyz.to_array().into_iter().flat_map(s15_fixed16));
+    tag
+}
+
+/// Multi-localised text with a single English record.
+fn text_tag(text: &str) -> Vec<u8> {
+    let utf16: Vec<u8> = text.encode_utf16().flat_map(u16::to_be_bytes).collect();
+
+    let mut tag = b"mluc\0\0\0\0".to_vec();
+    tag.extend(1u32.to_be_bytes());
+    tag.extend(12u32.to_be_bytes());
+    tag.extend(b"enUS");
+    tag.extend((utf16.len() as u32).to_be_bytes());
+    tag.extend(28u32.to_be_bytes());
+    tag.extend(utf16);
+    tag
+}
+
+fn curve_tag(params: [f64; 5]) -> Vec<u8> {
+    let mut tag = b"para\0\0\0\0".to_vec();
+    tag.extend(3u16.to_be_bytes());
+    tag.extend([0; 2]);
+    tag.extend(params.into_iter().flat_map(s15_fixed16));
+    tag
+}
+
+/// Matrix adapting colours from the white point `white` to D50 with the Bradford transform.
+fn adaptation(white: DVec3) -> DMat3 {
+    let bradford = BRADFORD.transpose();
+    let scale = (bradford * D50) / (bradford * white);
+    bradford.inverse() * DMat3::from_diagonal(scale) * bradford
+}
+
+/// ICC profile of `color_space`.
+pub fn profile(color_space: ColorSpace) -> Vec<u8> {
+    let xyz = |(x, y): (f64, f64)| DVec3::new(x / y, 1.0, (1.0 - x - y) / y);
+    let [r, g, b, white] = chromaticities(color_space).map(xyz);
+
+    // Scale the primaries so that they add up to the white point
+    let primaries = DMat3::from_cols(r, g, b);
+    let to_xyz = primaries * DMat3::from_diagonal(primaries.inverse() * white);
+    let adaptation = adaptation(white);
+    let colorants = adaptation * to_xyz;
+
+    let mut chad = b"sf32\0\0\0\0".to_vec();
+    chad.extend(adaptation.transpose().to_cols_array().into_iter().flat_map(s15_fixed16));
+
+    let curve = curve_tag(transfer(color_space));
+    let tags = [
+        (b"desc", text_tag(name(color_space))),
+        (b"cprt", text_tag("No copyright, use freely")),
+        (b"wtpt", xyz_tag(D50)),
+        (b"chad", chad),
+        (b"rXYZ", xyz_tag(colorants.x_axis)),
+        (b"gXYZ", xyz_tag(colorants.y_axis)),
+        (b"bXYZ", xyz_tag(colorants.z_axis)),
+        (b"rTRC", curve.clone()),
+        (b"gTRC", curve.clone()),
+        (b"bTRC", curve),
+    ];
+
+    // Tag data follows the header and the tag table, each tag aligned to four bytes
+    let mut table = (tags.len() as u32).to_be_bytes().to_vec();
+    let mut data = Vec::new();
+    let start = 128 + 4 + 12 * tags.len();
+    for (signature, tag) in &tags {
+        table.extend(*signature);
+        table.extend(((start + data.len()) as u32).to_be_bytes());
+        table.extend((tag.len() as u32).to_be_bytes());
+        data.extend(tag);
+        data.resize(data.len().next_multiple_of(4), 0);
+    }
+
+    let mut header = vec![0; 128];
+    header[0..4].copy_from_slice(&((start + data.len()) as u32).to_be_bytes());
+    header[8..12].copy_from_slice(&[4, 0x30, 0, 0]);
+    header[12..16].copy_from_slice(b"mntr");
+    header[16..20].copy_from_slice(b"RGB ");
+    header[20..24].copy_from_slice(b"XYZ ");
+    for (i, value) in [2024u16, 1, 1, 0, 0, 0].into_iter().enumerate() {
+        header[24 + 2 * i..26 + 2 * i].copy_from_slice(&value.to_be_bytes());
+    }
+    header[36..40].copy_from_slice(b"acsp");
+    for (i, value) in D50.to_array().into_iter().enumerate() {
+        header[68 + 4 * i..72 + 4 * i].copy_from_slice(&s15_fixed16(value));
+    }
+
+    [header, table, data].concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Data of the tag with `signature`.
+    fn tag<'a>(profile: &'a [u8], signature: &[u8; 4]) -> &'a [u8] {
+        let count = u32::from_be_bytes(profile[128..132].try_into().unwrap()) as usize;
+        let entry = profile[132..132 + 12 * count]
+            .chunks(12)
+            .find(|entry| &entry[..4] == signature)
+            .unwrap();
+        let offset = u32::from_be_bytes(entry[4..8].try_into().unwrap()) as usize;
+        let size = u32::from_be_bytes(entry[8..12].try_into().unwrap()) as usize;
+        &profile[offset..offset + size]
+    }
+
+    fn fixed(bytes: &[u8]) -> Vec<f64> {
+        bytes
+            .chunks(4)
+            .map(|v| i32::from_be_bytes(v.try_into().unwrap()) as f64 / 65536.0)
+            .collect()
+    }
+
+    #[test]
+    fn colorants_add_up_to_the_white_point() {
+        for color_space in [
+            ColorSpace::Srgb,
+            ColorSpace::LinearSrgb,
+            ColorSpace::DisplayP3,
+            ColorSpace::Rec2020,
+        ] {
+            let profile = profile(color_space);
+            assert_eq!(
+                u32::from_be_bytes(profile[..4].try_into().unwrap()) as usize,
+                profile.len()
+            );
+            assert_eq!(&profile[36..40], b"acsp");
+
+            let white: f64 = [b"rXYZ", b"gXYZ", b"bXYZ"]
+                .into_iter()
+                .map(|signature| DVec3::from_slice(&fixed(&tag(&profile, signature)[8..])))
+                .sum::<DVec3>()
+                .distance(D50);
+            assert!(white < 1e-4, "{color_space:?}: {white}");
+        }
+
+        // The well-known D50 colorants of sRGB
+        let red = fixed(&tag(&profile(ColorSpace::Srgb), b"rXYZ")[8..]);
+        assert!(
+            (red[0] - 0.4361).abs() < 1e-3 && (red[1] - 0.2225).abs() < 1e-3,
+            "{red:?}"
+        );
+    }
+
+    #[test]
+    fn curves_decode_the_transfer_function() {
+        for color_space in [ColorSpace::Srgb, ColorSpace::LinearSrgb, ColorSpace::Rec2020] {
+            let params = fixed(&tag(&profile(color_space), b"rTRC")[12..]);
+            let [g, a, b, c, d] = params[..] else { unreachable!() };
+
+            for linear in [0.001, 0.05, 0.18, 0.5, 1.0] {
+                let x = color_space.transfer(glam::Vec3::splat(linear as f32)).x as f64;
+                let decoded = if x >= d { (a * x + b).powf(g) } else { c * x };
+                assert!(
+                    (decoded - linear).abs() < 1e-3 * linear.max(0.01),
+                    "{color_space:?}: {linear} {decoded}"
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,42 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+
+/// Numerical method used to integrate photon geodesics.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Integrator {
+    /// Explicit Euler, one gradient evaluation per step.
+    #[default]
+    Euler = 0,
+    /// Classic fourth-order Runge-Kutta, four gradient evaluations per step.
+    Rk4 = 1,
+    /// Embedded Runge-Kutta 5(4) of Dormand and Prince. The step size is scaled to keep the local error estimate
+    /// below the tolerance set with [`crate::render::Renderer::set_tolerance`].
+    #[serde(alias = "rk45")]
+    DormandPrince = 2,
+    /// Generalised (implicit) leapfrog, which is symplectic for the non-separable geodesic Hamiltonian and keeps the
+    /// constraint drift of long integrations bounded. Its implicit stages are solved by fixed-point iteration, for
+    /// ten gradient evaluations per step.
+    Leapfrog = 3,
+}
+
+impl FromStr for Integrator {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "euler" => Ok(Integrator::Euler),
+            "rk4" => Ok(Integrator::Rk4),
+            "dormand-prince" | "rk45" => Ok(Integrator::DormandPrince),
+            "leapfrog" => Ok(Integrator::Leapfrog),
+            _ => Err(Error::UnknownOption {
+                name: "integrator",
+                value: s.to_string(),
+            }),
+        }
+    }
+}
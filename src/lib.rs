@@ -0,0 +1,30 @@
+//! Renders black holes by tracing photon geodesics through Kerr-Newman spacetimes, on the GPU or on the CPU.
+//!
+//! A [`Renderer`] is configured either with its setters or from a [`Scene`], which can also be stored as TOML. The
+//! accumulated image is tone mapped with [`tonemap`] and turned into displayable images with the helpers in
+//! [`output`].
+
+pub mod animation;
+pub mod black_hole;
+pub mod camera;
+mod cpu;
+pub mod diagnostics;
+pub mod disc;
+pub mod error;
+pub mod geometry;
+mod icc;
+pub mod integrator;
+pub mod metric;
+pub mod output;
+pub mod render;
+pub mod scene;
+pub mod shadow;
+pub mod sky;
+mod state;
+pub mod tonemap;
+mod types;
+
+pub use black_hole::BlackHole;
+pub use error::Error;
+pub use render::{Device, Renderer};
+pub use scene::Scene;
@@ -1,94 +1,151 @@
-use glam::{Mat4, Vec3, Vec4};
-use render::Renderer;
-
-mod render;
-mod state;
-mod types;
-
-fn step(edge: Vec3, x: Vec3) -> Vec3 {
-    Vec3::new(
-        if x.x < edge.x { 0.0 } else { 1.0 },
-        if x.y < edge.y { 0.0 } else { 1.0 },
-        if x.z < edge.z { 0.0 } else { 1.0 },
-    )
+use std::fmt;
+use std::path::Path;
+use std::process;
+
+use black_hole::geometry::{self, Geometry};
+use black_hole::output::{HdrFormat, HdrImage};
+use black_hole::{output, BlackHole, Device, Error, Renderer, Scene};
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser};
+use cli::{Cli, Command, RenderArgs};
+use image::Rgb;
+
+mod cli;
+
+fn main() {
+    let cli = Cli::parse();
+
+    match cli.command {
+        Some(Command::Info {
+            mass,
+            spin,
+            charge,
+            inclination,
+            shadow,
+        }) => info(BlackHole::new(mass, spin, charge), inclination, shadow),
+        None => render(cli.render),
+    }
 }
 
-fn smoothstep(edge0: Vec3, edge1: Vec3, mut x: Vec3) -> Vec3 {
-    x = Vec3::clamp((x - edge0) / (edge1 - edge0), Vec3::ZERO, Vec3::ONE);
-    x * x * (3.0 - 2.0 * x)
+/// Reports an invalid setting in the style of the argument parser and exits.
+fn fail(error: impl fmt::Display) -> ! {
+    Cli::command().error(ErrorKind::ValueValidation, error).exit()
 }
 
-fn mix(a: Vec3, b: Vec3, t: Vec3) -> Vec3 {
-    a * (1.0 - t) + b * t
+fn info(black_hole: BlackHole, inclination: f32, print_shadow: bool) {
+    if let Err(e) = black_hole.validate(true) {
+        fail(e);
+    }
+
+    print!("{}", Geometry::new(&black_hole));
+
+    let curve = geometry::shadow(&black_hole, inclination.to_radians(), 256);
+    if curve.is_empty() {
+        println!("{:<26}none", "shadow");
+        return;
+    }
+
+    let (min, max) = curve
+        .iter()
+        .fold((curve[0], curve[0]), |(min, max), &p| (min.min(p), max.max(p)));
+    println!("{:<26}{:.6}", "shadow width", max.x - min.x);
+    println!("{:<26}{:.6}", "shadow height", max.y - min.y);
+    println!("{:<26}{:.6}", "shadow center offset", 0.5 * (min.x + max.x));
+
+    if print_shadow {
+        println!();
+        for p in curve {
+            println!("{:.6} {:.6}", p.x, p.y);
+        }
+    }
 }
 
-fn linear_to_srgb(c: Vec3) -> Vec3 {
-    Vec3::clamp(
-        mix(
-            1.055 * Vec3::powf(c, 1.0 / 2.4) - Vec3::splat(0.055),
-            c * 12.92,
-            step(c, Vec3::splat(0.0031308)),
-        ),
-        Vec3::ZERO,
-        Vec3::ONE,
-    )
+fn render(args: RenderArgs) {
+    match try_render(&args) {
+        Ok(()) => {}
+        Err(e) if e.is_invalid_setting() => fail(e),
+        Err(e) => {
+            eprintln!("error: {e}");
+            process::exit(1);
+        }
+    }
 }
 
-fn tonemap(color: Vec3) -> Vec3 {
-    smoothstep(Vec3::ZERO, Vec3::ONE, 1.0 - Vec3::exp(-color * 1.0))
+fn try_render(args: &RenderArgs) -> Result<(), Error> {
+    let mut scene = match &args.scene {
+        Some(path) => Scene::load(path)?,
+        None => Scene::default(),
+    };
+    args.apply(&mut scene).unwrap_or_else(|e| e.exit());
+
+    if let Some(path) = &args.dump_scene {
+        scene.save(path)?;
+    }
+
+    let device = if args.cpu { Device::Cpu } else { Device::Auto };
+    let mut renderer = scene.build(device)?;
+    if let Some(reason) = renderer.fallback_reason() {
+        eprintln!("falling back to the CPU renderer: {reason}");
+    }
+
+    let Some(animation) = &scene.animation else {
+        return render_image(&scene, &mut renderer, &scene.output.path);
+    };
+
+    // All frames share the renderer, and with it the GPU resources. Each frame starts its own accumulation, even if
+    // the view has not changed, with a seed of its own so that the noise of consecutive frames is independent.
+    let path = animation.path(&scene.camera)?;
+    for (frame, time) in path.frame_times(animation.fps).enumerate() {
+        let (camera, position, focal_length) = path.view(time);
+        renderer.set_view(camera, position, focal_length);
+        renderer.set_seed(scene.sampling.seed.wrapping_add(frame as u32));
+        renderer.reset_accumulation();
+        render_image(
+            &scene,
+            &mut renderer,
+            &output::frame_path(&scene.output.path, frame as u32),
+        )?;
+    }
+
+    Ok(())
 }
 
-fn main() {
-    let (width, height) = (1920, 1080);
-
-    let position = Vec3::new(1.5139699, -16.080126, 2.293509);
-    let camera = Mat4::from_cols(
-        Vec4::new(0.98061466, 0.000000007450581, 0.19594617, 0.0),
-        Vec4::new(0.1948367, -0.10626423, -0.9750625, 0.0),
-        Vec4::new(0.020822048, 0.99433804, -0.1042043, 0.0),
-        Vec4::new(0.0, 0.0, 0.0, 1.0),
-    );
-
-    // let position = Vec3::new(2.3167892, -11.112907, 0.96180904);
-    // let camera = Mat4::from_cols(
-    //     Vec4::new(0.9854079, 0.0, 0.1702095, 0.0),
-    //     Vec4::new(0.17017217, 0.020942032, -0.9851917, 0.0),
-    //     Vec4::new(-0.0035645217, 0.99978065, 0.02063644, 0.0),
-    //     Vec4::new(0.0, 0.0, 0.0, 1.0),
-    // );
-
-    let mut renderer = Renderer::new(width, height);
-    renderer.set_render_skybox(false);
-    renderer.set_render_disc(true);
-    renderer.set_frames(16);
-    renderer.set_view(camera, position, 1.5);
-    renderer.render();
-
-    let data: Vec<_> = renderer
-        .target()
-        .chunks(4)
-        .map(|bytes| {
-            let b = <[u8; 4]>::try_from(bytes).unwrap();
-            f32::from_ne_bytes(b)
-        })
-        .collect();
-
-    let mut image: image::ImageBuffer<image::Rgb<u8>, Vec<u8>> = image::ImageBuffer::new(width, height);
-
-    for (i, pixel) in data.chunks(4).enumerate() {
-        let x = i as u32 % width;
-        let y = i as u32 / width;
-
-        let mut col = Vec3::new(pixel[0], pixel[1], pixel[2]);
-        col.x /= pixel[3];
-        col.y /= pixel[3];
-        col.z /= pixel[3];
-
-        col = linear_to_srgb(tonemap(col));
-
-        let col_unorm = (col.clamp(Vec3::ZERO, Vec3::ONE) * 255.0).as_u8vec3();
-        image.put_pixel(x, (height - 1) - y, image::Rgb(col_unorm.to_array()));
+/// Renders the current view of `renderer` and writes it to `output` with the output settings of `scene`.
+fn render_image(scene: &Scene, renderer: &mut Renderer, output: &Path) -> Result<(), Error> {
+    renderer.render()?;
+
+    let diagnostics = renderer.diagnostics();
+    if let Some(diagnostics) = diagnostics.as_ref().filter(|_| scene.output.diagnostics) {
+        output::write_diagnostics(diagnostics, output)?;
+    }
+
+    let shadow = renderer.shadow();
+    if let Some(report) = shadow.as_ref().and_then(|shadow| shadow.report) {
+        print!("{report}");
     }
 
-    image.save("black-hole.png").unwrap();
+    let (width, height) = (scene.output.width, scene.output.height);
+    let target = renderer.target();
+
+    if HdrFormat::from_path(output).is_some() {
+        let mut image = HdrImage::new(target, width, height);
+        for &channel in &scene.output.channels {
+            image.add_channel(channel, diagnostics.as_ref())?;
+        }
+
+        return image.save(output);
+    }
+
+    let tone_mapper = scene.output.tone_mapper(target)?;
+    let mut image = output::display_image(target, width, height, &tone_mapper, scene.output.color_space);
+
+    if let Some(shadow) = shadow {
+        shadow.draw(&mut image, Rgb([0.0, 1.0, 0.0]));
+    }
+
+    if scene.output.bit_depth == 16 {
+        output::save_display(&output::to_rgb16(&image), output, scene.output.color_space)
+    } else {
+        output::save_display(&output::to_rgb8(&image), output, scene.output.color_space)
+    }
 }
@@ -0,0 +1,80 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+
+/// Spacetime metric used to trace geodesics.
+///
+/// All metrics are expressed in Kerr-Schild coordinates with the spin axis along Z. The special cases ignore the
+/// parameters they do not support, so e.g. a Schwarzschild black hole is always uncharged and non-rotating.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Metric {
+    /// Uncharged, non-rotating black hole.
+    Schwarzschild = 0,
+    /// Charged, non-rotating black hole.
+    #[serde(alias = "reissner-nordström")]
+    ReissnerNordstrom = 1,
+    /// Uncharged, rotating black hole.
+    Kerr = 2,
+    /// Charged, rotating black hole.
+    #[default]
+    KerrNewman = 3,
+}
+
+impl Metric {
+    /// Whether the metric describes a rotating black hole.
+    pub fn has_spin(self) -> bool {
+        matches!(self, Metric::Kerr | Metric::KerrNewman)
+    }
+
+    /// Whether the metric describes a charged black hole.
+    pub fn has_charge(self) -> bool {
+        matches!(self, Metric::ReissnerNordstrom | Metric::KerrNewman)
+    }
+}
+
+/// Method used to compute the gradient of the Hamiltonian while integrating geodesics.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Gradient {
+    /// Exact derivatives of the Kerr-Schild form of the metric.
+    #[default]
+    Analytic,
+    /// Forward differences of the Lagrangian, kept for comparison with the analytic gradient.
+    FiniteDifference,
+}
+
+impl FromStr for Metric {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "schwarzschild" => Ok(Metric::Schwarzschild),
+            "reissner-nordstrom" | "reissner-nordström" => Ok(Metric::ReissnerNordstrom),
+            "kerr" => Ok(Metric::Kerr),
+            "kerr-newman" => Ok(Metric::KerrNewman),
+            _ => Err(Error::UnknownOption {
+                name: "metric",
+                value: s.to_string(),
+            }),
+        }
+    }
+}
+
+impl FromStr for Gradient {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "analytic" => Ok(Gradient::Analytic),
+            "finite-difference" => Ok(Gradient::FiniteDifference),
+            _ => Err(Error::UnknownOption {
+                name: "gradient",
+                value: s.to_string(),
+            }),
+        }
+    }
+}
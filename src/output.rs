@@ -0,0 +1,583 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use exr::prelude::{AnyChannel, AnyChannels, Encoding, FlatSamples, Image, Layer, LayerAttributes, WritableImage};
+use glam::{Mat3, Vec3};
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::codecs::tiff::TiffEncoder;
+use image::error::{EncodingError, ImageFormatHint};
+use image::{
+    DynamicImage, EncodableLayout, ImageBuffer, ImageEncoder, ImageError, ImageFormat, PixelWithColorType, Rgb,
+    Rgb32FImage, RgbImage, Rgba, Rgba32FImage,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::cpu;
+use crate::diagnostics::Diagnostics;
+use crate::error::Error;
+use crate::icc;
+use crate::tonemap::ToneMapper;
+
+fn step(edge: Vec3, x: Vec3) -> Vec3 {
+    Vec3::new(
+        if x.x < edge.x { 0.0 } else { 1.0 },
+        if x.y < edge.y { 0.0 } else { 1.0 },
+        if x.z < edge.z { 0.0 } else { 1.0 },
+    )
+}
+
+fn mix(a: Vec3, b: Vec3, t: Vec3) -> Vec3 {
+    a * (1.0 - t) + b * t
+}
+
+/// Encodes linear colour with the sRGB transfer function, clamped to `[0, 1]`.
+pub fn linear_to_srgb(c: Vec3) -> Vec3 {
+    Vec3::clamp(
+        mix(
+            1.055 * Vec3::powf(c, 1.0 / 2.4) - Vec3::splat(0.055),
+            c * 12.92,
+            step(c, Vec3::splat(0.0031308)),
+        ),
+        Vec3::ZERO,
+        Vec3::ONE,
+    )
+}
+
+/// Accumulated colour and sample count of each pixel of a render target, bottom row first.
+fn raw_pixels(target: &[u8]) -> impl Iterator<Item = [f32; 4]> + '_ {
+    target.chunks(16).map(|bytes| {
+        let mut pixel = [0.0; 4];
+        for (value, bytes) in pixel.iter_mut().zip(bytes.chunks(4)) {
+            *value = f32::from_ne_bytes(<[u8; 4]>::try_from(bytes).unwrap());
+        }
+        pixel
+    })
+}
+
+/// Accumulated colour of `pixel` divided by its sample count. Pixels whose samples were all discarded are black.
+fn average(pixel: [f32; 4]) -> Vec3 {
+    if pixel[3] > 0.0 {
+        Vec3::from_slice(&pixel) / pixel[3]
+    } else {
+        Vec3::ZERO
+    }
+}
+
+/// Averaged linear radiance of each pixel of the accumulated render target of [`crate::render::Renderer::target`],
+/// dividing by the sample count, with the bottom row first.
+pub fn linear_pixels(target: &[u8]) -> Vec<Vec3> {
+    raw_pixels(target).map(average).collect()
+}
+
+/// Linear sRGB to Display P3, both with the D65 white point, stored row by row.
+#[allow(clippy::excessive_precision)]
+const SRGB_TO_DISPLAY_P3: Mat3 = Mat3::from_cols_array(&[
+    0.8224621, 0.1775380, 0.0000000, 0.0331941, 0.9668058, 0.0000000, 0.0170827, 0.0723974, 0.9105199,
+]);
+/// Linear sRGB to Rec. 2020, stored row by row.
+#[allow(clippy::excessive_precision)]
+const SRGB_TO_REC2020: Mat3 = Mat3::from_cols_array(&[
+    0.6274040, 0.3292820, 0.0433136, 0.0690970, 0.9195400, 0.0113612, 0.0163916, 0.0880132, 0.8955950,
+]);
+
+/// Encodes linear colour with the Rec. 2020 transfer function, clamped to `[0, 1]`.
+fn linear_to_rec2020(c: Vec3) -> Vec3 {
+    const ALPHA: f32 = 1.099_296_8;
+    const BETA: f32 = 0.018_053_97;
+
+    Vec3::clamp(
+        mix(
+            ALPHA * Vec3::powf(c, 0.45) - Vec3::splat(ALPHA - 1.0),
+            c * 4.5,
+            step(c, Vec3::splat(BETA)),
+        ),
+        Vec3::ZERO,
+        Vec3::ONE,
+    )
+}
+
+/// Primaries and transfer function of tone-mapped images.
+///
+/// The renderer produces linear sRGB, with negative components for colours outside its gamut. It is converted to the
+/// primaries of the colour space before it is tone mapped and encoded, and images in any colour space but sRGB carry
+/// an ICC profile describing it. Floating point outputs always store linear sRGB.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ColorSpace {
+    /// sRGB primaries and transfer function.
+    #[default]
+    Srgb,
+    /// sRGB primaries without a transfer function.
+    LinearSrgb,
+    /// Display P3 primaries with the sRGB transfer function.
+    DisplayP3,
+    /// Rec. 2020 primaries and transfer function.
+    Rec2020,
+}
+
+impl ColorSpace {
+    /// Converts linear sRGB colour, which may lie outside the sRGB gamut, to the linear primaries of the colour space.
+    pub fn from_linear_srgb(self, color: Vec3) -> Vec3 {
+        match self {
+            ColorSpace::Srgb | ColorSpace::LinearSrgb => color,
+            ColorSpace::DisplayP3 => SRGB_TO_DISPLAY_P3.transpose() * color,
+            ColorSpace::Rec2020 => SRGB_TO_REC2020.transpose() * color,
+        }
+    }
+
+    /// Applies the transfer function to linear colour in the primaries of the colour space, clamping it to `[0, 1]`.
+    pub fn transfer(self, color: Vec3) -> Vec3 {
+        match self {
+            ColorSpace::Srgb | ColorSpace::DisplayP3 => linear_to_srgb(color),
+            ColorSpace::LinearSrgb => color.clamp(Vec3::ZERO, Vec3::ONE),
+            ColorSpace::Rec2020 => linear_to_rec2020(color),
+        }
+    }
+
+    /// Encodes tone-mapped linear sRGB colour, clamping it to `[0, 1]`.
+    pub fn encode(self, color: Vec3) -> Vec3 {
+        self.transfer(self.from_linear_srgb(color))
+    }
+}
+
+impl FromStr for ColorSpace {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "srgb" => Ok(ColorSpace::Srgb),
+            "linear-srgb" | "linear" => Ok(ColorSpace::LinearSrgb),
+            "display-p3" | "p3" => Ok(ColorSpace::DisplayP3),
+            "rec2020" | "rec-2020" => Ok(ColorSpace::Rec2020),
+            _ => Err(Error::UnknownOption {
+                name: "colour space",
+                value: s.to_string(),
+            }),
+        }
+    }
+}
+
+/// Converts the accumulated render target of [`crate::render::Renderer::target`] into a tone-mapped image encoded
+/// in `color_space`, with values in `[0, 1]`, dividing each pixel by its sample count.
+///
+/// The radiance is tone mapped in the primaries of `color_space`, so that colours outside the sRGB gamut are kept
+/// where the colour space can represent them.
+pub fn display_image(
+    target: &[u8],
+    width: u32,
+    height: u32,
+    tone_mapper: &ToneMapper,
+    color_space: ColorSpace,
+) -> Rgb32FImage {
+    let mut image = Rgb32FImage::new(width, height);
+
+    for (i, pixel) in linear_pixels(target).into_iter().enumerate() {
+        let x = i as u32 % width;
+        let y = i as u32 / width;
+
+        let col = color_space.transfer(tone_mapper.apply(color_space.from_linear_srgb(pixel)));
+        image.put_pixel(x, (height - 1) - y, Rgb(col.to_array()));
+    }
+
+    image
+}
+
+/// Converts the accumulated render target of [`crate::render::Renderer::target`] into a tone-mapped 8-bit sRGB
+/// image, dividing each pixel by its sample count.
+pub fn srgb_image(target: &[u8], width: u32, height: u32, tone_mapper: &ToneMapper) -> RgbImage {
+    to_rgb8(&display_image(target, width, height, tone_mapper, ColorSpace::Srgb))
+}
+
+/// Quantizes an image from [`display_image`] to 8 bits per channel.
+pub fn to_rgb8(image: &Rgb32FImage) -> RgbImage {
+    ImageBuffer::from_fn(image.width(), image.height(), |x, y| {
+        Rgb(image
+            .get_pixel(x, y)
+            .0
+            .map(|v| (v.clamp(0.0, 1.0) * 255.0).round() as u8))
+    })
+}
+
+/// Quantizes an image from [`display_image`] to 16 bits per channel.
+pub fn to_rgb16(image: &Rgb32FImage) -> ImageBuffer<Rgb<u16>, Vec<u16>> {
+    ImageBuffer::from_fn(image.width(), image.height(), |x, y| {
+        Rgb(image
+            .get_pixel(x, y)
+            .0
+            .map(|v| (v.clamp(0.0, 1.0) * 65535.0).round() as u16))
+    })
+}
+
+/// Whether the format selected by the extension of `path` can store 16 bits per channel.
+pub fn supports_16_bit(path: &Path) -> bool {
+    matches!(ImageFormat::from_path(path), Ok(ImageFormat::Png | ImageFormat::Tiff))
+}
+
+/// Whether the format selected by the extension of `path` can embed the ICC profile of a [`ColorSpace`].
+pub fn supports_color_profile(path: &Path) -> bool {
+    matches!(
+        ImageFormat::from_path(path),
+        Ok(ImageFormat::Png | ImageFormat::Tiff | ImageFormat::Jpeg)
+    )
+}
+
+/// Floating point image formats, which store the linear radiance instead of a tone-mapped image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HdrFormat {
+    /// OpenEXR, which can also hold the extra [`Channel`]s.
+    OpenExr,
+    /// Radiance RGBE.
+    Radiance,
+}
+
+impl HdrFormat {
+    /// Floating point format selected by the extension of `path`, if any.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_string_lossy().to_ascii_lowercase();
+        match extension.as_str() {
+            "exr" => Some(HdrFormat::OpenExr),
+            "hdr" => Some(HdrFormat::Radiance),
+            _ => None,
+        }
+    }
+}
+
+/// Per-pixel value written to OpenEXR next to the radiance.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Channel {
+    /// Number of samples accumulated in the pixel.
+    Samples,
+    /// Maximum Hamiltonian drift, see [`Diagnostics::hamiltonian_drift`].
+    Drift,
+    /// Mean number of integration steps.
+    Steps,
+    /// [`Termination`](crate::diagnostics::Termination) reason of the most recent sample, zero if there is none.
+    Termination,
+}
+
+impl Channel {
+    /// Name of the channel in the OpenEXR file.
+    pub fn name(self) -> &'static str {
+        match self {
+            Channel::Samples => "samples",
+            Channel::Drift => "drift",
+            Channel::Steps => "steps",
+            Channel::Termination => "termination",
+        }
+    }
+
+    /// Whether the channel is taken from the integration diagnostics, which must then be recorded.
+    pub fn needs_diagnostics(self) -> bool {
+        self != Channel::Samples
+    }
+}
+
+impl FromStr for Channel {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "samples" => Ok(Channel::Samples),
+            "drift" => Ok(Channel::Drift),
+            "steps" => Ok(Channel::Steps),
+            "termination" => Ok(Channel::Termination),
+            _ => Err(Error::UnknownOption {
+                name: "channel",
+                value: s.to_string(),
+            }),
+        }
+    }
+}
+
+/// Averaged linear radiance of a render with optional extra channels, stored top row first.
+pub struct HdrImage {
+    radiance: Rgba32FImage,
+    samples: Vec<f32>,
+    channels: Vec<(Channel, Vec<f32>)>,
+}
+
+impl HdrImage {
+    /// Divides the accumulated render target of [`crate::render::Renderer::target`] by the sample count of each
+    /// pixel. The alpha channel of the radiance is one.
+    pub fn new(target: &[u8], width: u32, height: u32) -> Self {
+        let mut radiance = Rgba32FImage::new(width, height);
+        let mut samples = vec![0.0; (width * height) as usize];
+
+        for (i, pixel) in raw_pixels(target).enumerate() {
+            let x = i as u32 % width;
+            let y = (height - 1) - i as u32 / width;
+
+            radiance.put_pixel(x, y, Rgba(average(pixel).extend(1.0).to_array()));
+            samples[(y * width + x) as usize] = pixel[3];
+        }
+
+        Self {
+            radiance,
+            samples,
+            channels: Vec::new(),
+        }
+    }
+
+    pub fn radiance(&self) -> &Rgba32FImage {
+        &self.radiance
+    }
+
+    /// Adds an extra channel, taking the diagnostic ones from `diagnostics`, which must have been recorded for the
+    /// same render.
+    pub fn add_channel(&mut self, channel: Channel, diagnostics: Option<&Diagnostics>) -> Result<(), Error> {
+        let values = match (channel, diagnostics) {
+            (Channel::Samples, _) => self.samples.clone(),
+            (_, None) => return Err(Error::MissingDiagnostics(channel.name())),
+            (_, Some(diagnostics)) => {
+                let (width, height) = diagnostics.size();
+                (0..height)
+                    .flat_map(|y| (0..width).map(move |x| (x, y)))
+                    .map(|(x, y)| match channel {
+                        Channel::Drift => diagnostics.hamiltonian_drift(x, y),
+                        Channel::Steps => diagnostics.steps(x, y),
+                        _ => diagnostics.termination(x, y).map_or(0.0, |t| t as u32 as f32),
+                    })
+                    .collect()
+            }
+        };
+
+        self.channels.push((channel, values));
+        Ok(())
+    }
+
+    /// Saves the image as OpenEXR or Radiance HDR, depending on the extension of `path`. Only OpenEXR can hold
+    /// extra channels.
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        match HdrFormat::from_path(path) {
+            Some(HdrFormat::OpenExr) if !self.channels.is_empty() => self.save_exr(path),
+            Some(HdrFormat::OpenExr) => save(&self.radiance, path),
+            Some(HdrFormat::Radiance) if self.channels.is_empty() => {
+                save(&DynamicImage::from(self.radiance.clone()).into_rgb32f(), path)
+            }
+            _ => Err(Error::UnsupportedChannels(path.to_path_buf())),
+        }
+    }
+
+    fn save_exr(&self, path: &Path) -> Result<(), Error> {
+        let mut channels: Vec<_> = ["R", "G", "B", "A"]
+            .into_iter()
+            .enumerate()
+            .map(|(c, name)| {
+                let values = self.radiance.pixels().map(|pixel| pixel[c]).collect();
+                AnyChannel::new(name, FlatSamples::F32(values))
+            })
+            .collect();
+        channels.extend(
+            self.channels
+                .iter()
+                .map(|(channel, values)| AnyChannel::new(channel.name(), FlatSamples::F32(values.clone()))),
+        );
+
+        let size = (self.radiance.width() as usize, self.radiance.height() as usize);
+        let layer = Layer::new(
+            size,
+            LayerAttributes::default(),
+            Encoding::FAST_LOSSLESS,
+            AnyChannels::sort(channels.into()),
+        );
+
+        Image::from_layer(layer)
+            .write()
+            .to_file(path)
+            .map_err(|error| Error::Image {
+                path: path.to_path_buf(),
+                error: ImageError::Encoding(EncodingError::new(ImageFormatHint::Exact(ImageFormat::OpenExr), error)),
+            })
+    }
+}
+
+/// Path next to `output` with `suffix` appended to its file stem, e.g. `render-drift.png` for `render.png`.
+pub fn sibling_path(output: &Path, suffix: &str) -> PathBuf {
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+    output.with_file_name(format!("{stem}-{suffix}"))
+}
+
+/// Path of frame `frame` of a sequence written to `output`, e.g. `render-0042.png` for `render.png`.
+pub fn frame_path(output: &Path, frame: u32) -> PathBuf {
+    let extension = output.extension().map(|extension| extension.to_string_lossy());
+    match extension {
+        Some(extension) => sibling_path(output, &format!("{frame:04}.{extension}")),
+        None => sibling_path(output, &format!("{frame:04}")),
+    }
+}
+
+/// Saves an image in the format selected by the extension of `path`.
+pub fn save<P, C>(image: &ImageBuffer<P, C>, path: &Path) -> Result<(), Error>
+where
+    P: PixelWithColorType,
+    [P::Subpixel]: EncodableLayout,
+    C: Deref<Target = [P::Subpixel]>,
+{
+    image.save(path).map_err(|error| Error::Image {
+        path: path.to_path_buf(),
+        error,
+    })
+}
+
+/// Saves a tone-mapped image encoded in `color_space` in the format selected by the extension of `path`, embedding
+/// an ICC profile unless the colour space is sRGB, which viewers assume for untagged images.
+pub fn save_display<P, C>(image: &ImageBuffer<P, C>, path: &Path, color_space: ColorSpace) -> Result<(), Error>
+where
+    P: PixelWithColorType,
+    [P::Subpixel]: EncodableLayout,
+    C: Deref<Target = [P::Subpixel]>,
+{
+    if color_space == ColorSpace::Srgb {
+        return save(image, path);
+    }
+
+    let image_error = |error| Error::Image {
+        path: path.to_path_buf(),
+        error,
+    };
+    let unsupported = || Error::UnsupportedColorSpace(path.to_path_buf());
+
+    let format = ImageFormat::from_path(path).map_err(image_error)?;
+    let file = File::create(path).map_err(|error| Error::Io {
+        path: path.to_path_buf(),
+        error,
+    })?;
+    let mut file = BufWriter::new(file);
+    let profile = icc::profile(color_space);
+
+    match format {
+        ImageFormat::Png => {
+            let mut encoder = PngEncoder::new(&mut file);
+            encoder.set_icc_profile(profile).map_err(|_| unsupported())?;
+            image.write_with_encoder(encoder)
+        }
+        ImageFormat::Tiff => {
+            let mut encoder = TiffEncoder::new(&mut file);
+            encoder.set_icc_profile(profile).map_err(|_| unsupported())?;
+            image.write_with_encoder(encoder)
+        }
+        ImageFormat::Jpeg => {
+            let mut encoder = JpegEncoder::new(&mut file);
+            encoder.set_icc_profile(profile).map_err(|_| unsupported())?;
+            image.write_with_encoder(encoder)
+        }
+        _ => return Err(unsupported()),
+    }
+    .map_err(image_error)?;
+
+    file.flush().map_err(|error| Error::Io {
+        path: path.to_path_buf(),
+        error,
+    })
+}
+
+/// Writes the raw diagnostics as OpenEXR and the drift, step count and termination images as PNG next to `output`.
+pub fn write_diagnostics(diagnostics: &Diagnostics, output: &Path) -> Result<(), Error> {
+    save(&diagnostics.raw_image(), &sibling_path(output, "diagnostics.exr"))?;
+    save(
+        &diagnostics.hamiltonian_drift_image(),
+        &sibling_path(output, "drift.png"),
+    )?;
+    save(&diagnostics.steps_image(cpu::STEPS), &sibling_path(output, "steps.png"))?;
+    save(
+        &diagnostics.termination_image(),
+        &sibling_path(output, "termination.png"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hdr_image_averages_samples_top_row_first() {
+        // Bottom row first, as read back from the render target
+        let raw = [
+            [2.0, 4.0, 6.0, 2.0],
+            [1.0, 1.0, 1.0, 1.0],
+            [0.0, 0.0, 3.0, 3.0],
+            [8.0, 0.0, 0.0, 4.0],
+        ];
+        let target: Vec<u8> = raw.iter().flatten().flat_map(|v: &f32| v.to_ne_bytes()).collect();
+
+        let mut image = HdrImage::new(&target, 2, 2);
+        assert_eq!(image.radiance().get_pixel(0, 1).0, [1.0, 2.0, 3.0, 1.0]);
+        assert_eq!(image.radiance().get_pixel(1, 0).0, [2.0, 0.0, 0.0, 1.0]);
+
+        image.add_channel(Channel::Samples, None).unwrap();
+        assert_eq!(image.channels[0].1, [3.0, 4.0, 2.0, 1.0]);
+        assert!(matches!(
+            image.add_channel(Channel::Steps, None),
+            Err(Error::MissingDiagnostics("steps"))
+        ));
+        assert!(matches!(
+            image.save(Path::new("render.hdr")),
+            Err(Error::UnsupportedChannels(_))
+        ));
+    }
+
+    #[test]
+    fn pixels_without_samples_are_black() {
+        let raw = [[0.0, 0.0, 0.0, 0.0], [1.0, 2.0, 3.0, 1.0]];
+        let target: Vec<u8> = raw.iter().flatten().flat_map(|v: &f32| v.to_ne_bytes()).collect();
+
+        assert_eq!(linear_pixels(&target), [Vec3::ZERO, Vec3::new(1.0, 2.0, 3.0)]);
+        let image = HdrImage::new(&target, 2, 1);
+        assert_eq!(image.radiance().get_pixel(0, 0).0, [0.0, 0.0, 0.0, 1.0]);
+        assert_eq!(image.samples, [0.0, 1.0]);
+    }
+
+    #[test]
+    fn color_spaces_keep_white_and_grey_neutral() {
+        for color_space in [
+            ColorSpace::Srgb,
+            ColorSpace::LinearSrgb,
+            ColorSpace::DisplayP3,
+            ColorSpace::Rec2020,
+        ] {
+            let white = color_space.encode(Vec3::ONE);
+            assert!(
+                (white - Vec3::ONE).abs().max_element() < 1e-4,
+                "{color_space:?}: {white}"
+            );
+
+            let grey = color_space.encode(Vec3::splat(0.18));
+            assert!(
+                grey.max_element() - grey.min_element() < 1e-4,
+                "{color_space:?}: {grey}"
+            );
+        }
+
+        // Saturated sRGB red lies inside the wider gamuts, and so does a deeper red outside the sRGB gamut
+        let red = ColorSpace::Rec2020.encode(Vec3::X);
+        assert!(red.x < 1.0 && red.y > 0.0 && red.z > 0.0, "{red}");
+        let red = ColorSpace::Rec2020.from_linear_srgb(Vec3::new(1.0, -0.05, -0.01));
+        assert!(red.min_element() > 0.0, "{red}");
+    }
+
+    #[test]
+    fn wide_gamut_images_embed_a_profile() {
+        let dir = std::env::temp_dir().join(format!("black-hole-output-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (png, tiff) = (dir.join("p3.png"), dir.join("p3.tiff"));
+
+        let image = to_rgb16(&Rgb32FImage::from_pixel(2, 2, Rgb([0.5, 0.25, 1.0])));
+        save_display(&image, &png, ColorSpace::DisplayP3).unwrap();
+        save_display(&image, &tiff, ColorSpace::DisplayP3).unwrap();
+
+        let mut decoder = image::ImageReader::open(&png).unwrap().into_decoder().unwrap();
+        let png_profile = image::ImageDecoder::icc_profile(&mut decoder).unwrap();
+        // The TIFF decoder does not read profiles back, but TIFF stores them uncompressed
+        let tiff = std::fs::read(&tiff).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let profile = icc::profile(ColorSpace::DisplayP3);
+        assert_eq!(png_profile, Some(profile.clone()));
+        assert!(tiff.windows(profile.len()).any(|window| window == profile));
+    }
+}
@@ -14,13 +14,78 @@ struct View {
     resolution: vec2<u32>,
     frame_count: u32,
     flags: u32,
+    // Offsets the random sequence of every pixel, zero for the original sequence
+    seed: u32,
+    // Multiplies the radiance of the sky
+    sky_intensity: f32,
+    // Rotates world directions into the frame of the sky texture
+    sky_rotation: mat4x4<f32>,
+    // Procedural sky (see `procedural_sky()`)
+    star_seed: u32,
+    star_grid: u32,
+    star_probability: f32,
+    star_flux: f32,
+    star_size: f32,
+    galaxy_brightness: f32,
+};
+
+struct Spacetime {
+    // Black hole mass
+    mass: f32,
+    // Black hole spin factor (J/M)
+    spin: f32,
+    // Black hole charge
+    charge: f32,
+    // Radius of the sphere of influence in which gravitational lensing occurs
+    lensing_radius: f32,
+    // Metric used for the black hole (see `metric()`)
+    metric: u32,
+    // Outer event horizon radius, or zero if there is none
+    horizon: f32,
+    // Radius of the innermost circular photon orbit, inside which photons cannot escape, or zero if there is none
+    photon_orbit: f32,
+    // Magnitude of the momentum above which a photon has diverged, see `divergence_momentum()` of the CPU backend
+    max_momentum: f32,
+};
+
+struct Integration {
+    // Integrator used for photon geodesics (see `integrate()`)
+    method: u32,
+    // Local error tolerance of adaptive integrators
+    tolerance: f32,
+};
+
+struct Disc {
+    // Model used to apply the redshift of the emitted light (see `sample_volume()`)
+    redshift: u32,
+    // Radius of the innermost stable circular orbit, inside which the gas plunges into the black hole
+    isco: f32,
+    // Specific energy and angular momentum of the gas at the ISCO, conserved while plunging
+    isco_energy: f32,
+    isco_angular_momentum: f32,
+    // Shape of the accretion disc
+    model: u32,
+    // Outer radius of the accretion disc, which depends on its model
+    outer_radius: f32,
+    // Peak temperature of the thin disc
+    temperature: f32,
+    // Novikov-Thorne flux of the thin disc from the ISCO to the outer radius, normalized to a maximum of one
+    flux: array<vec4<f32>, 16>,
 };
 
 @group(0) @binding(0)
 var last_frame: texture_2d<f32>;
+@group(0) @binding(1)
+var last_diagnostics: texture_2d<f32>;
 
 @group(1) @binding(0)
 var<uniform> view: View;
+@group(1) @binding(1)
+var<uniform> spacetime: Spacetime;
+@group(1) @binding(2)
+var<uniform> integration: Integration;
+@group(1) @binding(3)
+var<uniform> disc: Disc;
 
 @group(2) @binding(0)
 var sky_texture: texture_cube<f32>;
@@ -210,22 +275,12 @@ fn diag(a: vec4<f32>) -> mat4x4<f32> {
                   0.0, 0.0, 0.0, a.w);
 }
 
-// Radius of the sphere of influence in which gravitational lensing occurs
-const cdist: f32 = 120.0;
-// Black hole spin factor (J/M^2)
-const a: f32 = 0.3;
-// Black hole mass
-const m: f32 = 1.0;
-// Black hole charge
-const Q: f32 = 0.2;
 // Epsilon when approximating gradients
 const eps: f32 = 0.005;
 const dx: vec2<f32> = vec2<f32>(0.0, eps);
 // Max ray bounces (only applies to volumetric accretion disc)
 const max_bounces: u32 = 4;
 
-// Radius of the accretion disc
-const disc_radius: f32 = 10.0;
 // Height of the accretion disc
 const disc_height: f32 = 0.8;
 // Falloff of the volumetric accretion disc (radial, vertical)
@@ -238,6 +293,17 @@ const disc_temperature_scale: f32 = 4000.0;
 const disc_temperature_offset: f32 = 2000.0;
 // Scale of noise on the accretion disc
 const disc_radial_scale: f32 = 8.0;
+// Range of temperatures over which the black-body approximation is accurate
+const blackbody_range: vec2<f32> = vec2<f32>(1000.0, 15000.0);
+
+// Brightness of the thin accretion disc at its peak flux
+const thin_disc_brightness: f32 = 4.0;
+
+const DISC_MODEL_THIN: u32 = 1u;
+
+const REDSHIFT_OFF: u32 = 0u;
+const REDSHIFT_SPECIFIC: u32 = 1u;
+const REDSHIFT_BOLOMETRIC: u32 = 2u;
 
 /// Minimum timestep for spacetime pathtracer
 const dt_min: f32 = 0.01;
@@ -248,6 +314,22 @@ const dt_max: f32 = 0.1; // can be 10.0 for no accretion disc
 const steps: u32 = 2048u; // can be 128u for no accretion disc
 
 var<private> dt: f32 = dt_min;
+// Factor applied to the timestep by adaptive integrators
+var<private> step_scale: f32 = 1.0;
+// Length of the last step taken by `integrate`, which adaptive integrators may shrink
+var<private> accepted_step: f32 = 0.0;
+
+const INTEGRATOR_RK4: u32 = 1u;
+const INTEGRATOR_DORMAND_PRINCE: u32 = 2u;
+const INTEGRATOR_LEAPFROG: u32 = 3u;
+
+// Bounds of the adaptive step scale
+const step_scale_min: f32 = 0.015625;
+const step_scale_max: f32 = 8.0;
+// Maximum number of rejected steps before a step is accepted regardless of its error
+const max_rejections: u32 = 8u;
+// Fixed-point iterations solving each implicit stage of the generalised leapfrog
+const leapfrog_iterations: u32 = 4u;
 
 fn sphere_intersect(ro: vec3<f32>, rd: vec3<f32>, sphere: vec4<f32>) -> f32 {
     let oc = ro - sphere.xyz;
@@ -261,12 +343,38 @@ fn sphere_intersect(ro: vec3<f32>, rd: vec3<f32>, sphere: vec4<f32>) -> f32 {
     return -b - sqrt(h);
 }
 
+// Color of black-body radiation emitted at the given temperature as seen by the camera, normalized to its brightest
+// channel before the intensity is scaled with the redshift factor. Colors outside the sRGB gamut keep their negative
+// components, so that wider output color spaces can show them.
+fn emission(temperature: f32, redshift: f32) -> vec3<f32> {
+    var e = xyz2rgb(blackbody(clamp(redshift * temperature, blackbody_range.x, blackbody_range.y)));
+    e /= max(max(max(e.r, e.g), e.b), 0.01);
+
+    // I_ν / ν³ is invariant along the ray, so the specific intensity scales with g³ and the bolometric one with g⁴
+    switch (disc.redshift) {
+        case REDSHIFT_SPECIFIC: {
+            e *= redshift * redshift * redshift;
+        }
+        case REDSHIFT_BOLOMETRIC: {
+            e *= redshift * redshift * redshift * redshift;
+        }
+        default: {}
+    }
+
+    return e;
+}
+
 struct SampleVolumeOut {
     c: vec3<f32>,
     e: vec3<f32>,
     v: f32,
 };
 
+// Whether `p` lies within the bounds of the volumetric disc, outside of which it neither emits nor absorbs.
+fn inside_volume(p: vec3<f32>) -> bool {
+    return dot(p.xy, p.xy) <= disc.outer_radius * disc.outer_radius && p.z * p.z <= disc_height * disc_height;
+}
+
 fn sample_volume(p: vec3<f32>, redshift: f32) -> SampleVolumeOut {
     var out: SampleVolumeOut;
 
@@ -275,7 +383,7 @@ fn sample_volume(p: vec3<f32>, redshift: f32) -> SampleVolumeOut {
     out.v = 0.0;
 
     // Reject if not hit disc
-    if (dot(p.xy, p.xy) > disc_radius * disc_radius || p.z * p.z > disc_height * disc_height) {
+    if (!inside_volume(p)) {
         return out;
     };
 
@@ -284,10 +392,9 @@ fn sample_volume(p: vec3<f32>, redshift: f32) -> SampleVolumeOut {
     let d_falloff = length(disc_falloff.xxy * p);
     let e_falloff = length(disc_emission_falloff.xxy * p);
 
-    // Sample the color temperature of the accretion disc (with some random jitter) and normalize
+    // Sample the color temperature of the accretion disc (with some random jitter)
     let t = rand();
-    out.e = xyz2rgb(blackbody((disc_temperature_scale * t * t) + disc_temperature_offset));
-    out.e = clamp(out.e / max(max(max(out.e.r, out.e.g), out.e.b), 0.01), vec3(0.0), vec3(1.0));
+    out.e = emission((disc_temperature_scale * t * t) + disc_temperature_offset, redshift);
 
     // Account for density and emission falloff near edges of disc
     out.e *= 128.0 * max(n0 - e_falloff, 0.0) / (dot(0.5 * p, 0.5 * p) + 0.05);
@@ -296,17 +403,163 @@ fn sample_volume(p: vec3<f32>, redshift: f32) -> SampleVolumeOut {
     return out;
 }
 
-// Kerr-Newman metric in Kerr-Schild coordinates for spinning charged black hole rotating around the Z-axis.
-fn metric(x: vec4<f32>) -> mat4x4<f32> {
-    let p = x.yzw;
+const METRIC_SCHWARZSCHILD: u32 = 0u;
+const METRIC_REISSNER_NORDSTROM: u32 = 1u;
+
+// Boyer-Lindquist radius of a point given in Kerr-Schild coordinates.
+fn radius(p: vec3<f32>) -> f32 {
+    let a = spacetime.spin;
     let rho = dot(p, p) - a * a;
     let r2 = 0.5 * (rho + sqrt(rho * rho + 4.0 * a * a * p.z * p.z));
-    let r = sqrt(r2);
+    return sqrt(r2);
+}
+
+// Schwarzschild metric in Kerr-Schild coordinates for uncharged, non-rotating black hole.
+fn metric_schwarzschild(x: vec4<f32>) -> mat4x4<f32> {
+    let m = spacetime.mass;
+    let cdist = spacetime.lensing_radius;
+
+    let p = x.yzw;
+    let r = length(p);
+    let k = vec4(1.0, p / r);
+    let f = smoothstep(cdist * 0.5, 0.0, r) * 2.0 * m / r;
+    return f * mat4x4(k.x * k, k.y * k, k.z * k, k.w * k) + diag(vec4(-1.0, 1.0, 1.0, 1.0));
+}
+
+// Reissner-Nordström metric in Kerr-Schild coordinates for charged, non-rotating black hole.
+fn metric_reissner_nordstrom(x: vec4<f32>) -> mat4x4<f32> {
+    let m = spacetime.mass;
+    let Q = spacetime.charge;
+    let cdist = spacetime.lensing_radius;
+
+    let p = x.yzw;
+    let r = length(p);
+    let k = vec4(1.0, p / r);
+    let f = smoothstep(cdist * 0.5, 0.0, r) * (2.0 * m * r - Q * Q) / (r * r);
+    return f * mat4x4(k.x * k, k.y * k, k.z * k, k.w * k) + diag(vec4(-1.0, 1.0, 1.0, 1.0));
+}
+
+// Kerr-Newman metric in Kerr-Schild coordinates for spinning charged black hole rotating around the Z-axis.
+// Also used for the Kerr metric, whose charge is always zero.
+fn metric_kerr_newman(x: vec4<f32>) -> mat4x4<f32> {
+    let a = spacetime.spin;
+    let m = spacetime.mass;
+    let Q = spacetime.charge;
+    let cdist = spacetime.lensing_radius;
+
+    let p = x.yzw;
+    let r = radius(p);
+    let r2 = r * r;
     let k = vec4(1.0, (r * p.x + a * p.y) / (r2 + a * a), (r * p.y - a * p.x) / (r2 + a * a), p.z / r);
     let f = smoothstep(cdist * 0.5, 0.0, r) * r2 * (2.0 * m * r - Q * Q) / (r2 * r2 + a * a * p.z * p.z);
     return f * mat4x4(k.x * k, k.y * k, k.z * k, k.w * k) + diag(vec4(-1.0, 1.0, 1.0, 1.0));
 }
 
+fn metric(x: vec4<f32>) -> mat4x4<f32> {
+    switch (spacetime.metric) {
+        case METRIC_SCHWARZSCHILD: {
+            return metric_schwarzschild(x);
+        }
+        case METRIC_REISSNER_NORDSTROM: {
+            return metric_reissner_nordstrom(x);
+        }
+        default: {
+            return metric_kerr_newman(x);
+        }
+    }
+}
+
+// Kerr-Schild decomposition g = η + f k⊗k of the metric, with the spatial derivatives of f and k.
+struct KerrSchild {
+    f: f32,
+    k: vec4<f32>,
+    df: vec3<f32>,
+    // Column i holds ∂k/∂x_i
+    dk: mat3x4<f32>,
+};
+
+// Derivative of smoothstep(cdist * 0.5, 0.0, r) with respect to r.
+fn lensing_falloff_derivative(r: f32) -> f32 {
+    let cdist = spacetime.lensing_radius;
+    let t = clamp(1.0 - 2.0 * r / cdist, 0.0, 1.0);
+    return -12.0 * t * (1.0 - t) / cdist;
+}
+
+// Kerr-Schild decomposition for the Schwarzschild and Reissner-Nordström metrics.
+fn kerr_schild_spherical(p: vec3<f32>) -> KerrSchild {
+    let m = spacetime.mass;
+    let Q = spacetime.charge;
+    let cdist = spacetime.lensing_radius;
+
+    let r = length(p);
+    let n = p / r;
+
+    let s = smoothstep(cdist * 0.5, 0.0, r);
+    let h = (2.0 * m * r - Q * Q) / (r * r);
+    let dh = (2.0 * Q * Q - 2.0 * m * r) / (r * r * r);
+
+    var out: KerrSchild;
+    out.f = s * h;
+    out.k = vec4(1.0, n);
+    out.df = (lensing_falloff_derivative(r) * h + s * dh) * n;
+    out.dk = mat3x4(
+        vec4(0.0, (vec3(1.0, 0.0, 0.0) - n.x * n) / r),
+        vec4(0.0, (vec3(0.0, 1.0, 0.0) - n.y * n) / r),
+        vec4(0.0, (vec3(0.0, 0.0, 1.0) - n.z * n) / r),
+    );
+    return out;
+}
+
+// Kerr-Schild decomposition for the Kerr and Kerr-Newman metrics.
+fn kerr_schild_kerr_newman(p: vec3<f32>) -> KerrSchild {
+    let a = spacetime.spin;
+    let m = spacetime.mass;
+    let Q = spacetime.charge;
+    let cdist = spacetime.lensing_radius;
+
+    let rho = dot(p, p) - a * a;
+    let sq = sqrt(rho * rho + 4.0 * a * a * p.z * p.z);
+    let r2 = 0.5 * (rho + sq);
+    let r = sqrt(r2);
+    let dr = (r2 * p + vec3(0.0, 0.0, a * a * p.z)) / (r * sq);
+
+    let ra = r2 + a * a;
+    let k = vec4(1.0, (r * p.x + a * p.y) / ra, (r * p.y - a * p.x) / ra, p.z / r);
+    let dk1 = (p.x * dr + vec3(r, a, 0.0) - 2.0 * r * k.y * dr) / ra;
+    let dk2 = (p.y * dr + vec3(-a, r, 0.0) - 2.0 * r * k.z * dr) / ra;
+    let dk3 = (vec3(0.0, 0.0, 1.0) - k.w * dr) / r;
+
+    let s = smoothstep(cdist * 0.5, 0.0, r);
+    let num = r2 * (2.0 * m * r - Q * Q);
+    let den = r2 * r2 + a * a * p.z * p.z;
+    let dnum = (6.0 * m * r2 - 2.0 * Q * Q * r) * dr;
+    let dden = 4.0 * r2 * r * dr + vec3(0.0, 0.0, 2.0 * a * a * p.z);
+    let h = num / den;
+    let dh = (dnum * den - num * dden) / (den * den);
+
+    var out: KerrSchild;
+    out.f = s * h;
+    out.k = k;
+    out.df = lensing_falloff_derivative(r) * h * dr + s * dh;
+    out.dk = mat3x4(
+        vec4(0.0, dk1.x, dk2.x, dk3.x),
+        vec4(0.0, dk1.y, dk2.y, dk3.y),
+        vec4(0.0, dk1.z, dk2.z, dk3.z),
+    );
+    return out;
+}
+
+fn kerr_schild(p: vec3<f32>) -> KerrSchild {
+    switch (spacetime.metric) {
+        case METRIC_SCHWARZSCHILD, METRIC_REISSNER_NORDSTROM: {
+            return kerr_schild_spherical(p);
+        }
+        default: {
+            return kerr_schild_kerr_newman(p);
+        }
+    }
+}
+
 fn lagrangian(dxdt: vec4<f32>, x: vec4<f32>) -> f32 {
     let g = metric(x);
     return dot(g * dxdt, dxdt);
@@ -324,37 +577,175 @@ fn dxdt_from_momentum(p: vec4<f32>, x: vec4<f32>) -> vec4<f32> {
     return inverse(metric(x)) * p;
 }
 
-fn update_dt(p: vec4<f32>, x: vec4<f32>) -> bool {
-    let pos = x.yzw;
-    let rho = dot(pos, pos) - a * a;
-    let r2 = 0.5 * (rho + sqrt(rho * rho + 4.0 * a * a * pos.z * pos.z));
-    let r = sqrt(r2);
+// Four-velocity of an observer at rest in the Kerr-Schild coordinates. Static observers cannot exist inside the
+// ergosphere, where the velocity is clamped so that light emitted there is redshifted away.
+fn static_velocity(x: vec4<f32>) -> vec4<f32> {
+    let g = metric(x);
+    return vec4(inverseSqrt(max(-g[0][0], 1e-4)), 0.0, 0.0, 0.0);
+}
+
+// Angular velocity dφ/dt of the prograde circular orbit in the equatorial plane at Boyer-Lindquist radius r.
+fn keplerian_angular_velocity(r: f32) -> f32 {
+    let a = spacetime.spin;
+    let m = spacetime.mass;
+    let Q = spacetime.charge;
+
+    let s = sqrt(max(m * r - Q * Q, 0.0));
+    return s / (r * r + a * s);
+}
+
+// Four-velocity of the gas in the accretion disc. Outside the ISCO the gas follows circular orbits around the spin
+// axis, inside it plunges towards the horizon with the energy and angular momentum of the ISCO.
+fn disc_velocity(x: vec4<f32>) -> vec4<f32> {
+    let a = spacetime.spin;
+    let g = metric(x);
+
+    let p = x.yzw;
+    let r = radius(p);
+
+    // Killing vectors of time translation and rotation around the spin axis
+    let t = vec4(1.0, 0.0, 0.0, 0.0);
+    let phi = vec4(0.0, -p.y, p.x, 0.0);
+
+    if (r >= disc.isco) {
+        let v = t + keplerian_angular_velocity(r) * phi;
+        let norm = -dot(g * v, v);
+        if (norm <= 0.0) {
+            return static_velocity(x);
+        }
+
+        return v * inverseSqrt(norm);
+    }
 
-    dt = mix(dt_min, dt_max, pow(max(r - 1.0, 0.0) / cdist, 1.0));
+    // Solve for u = α t + β φ + γ n with g(u, t) = -E, g(u, φ) = L and g(u, u) = -1, where n points radially
+    let n = vec4(0.0, (r * p.x + a * p.y) / (r * r + a * a), (r * p.y - a * p.x) / (r * r + a * a), p.z / r);
 
-    if (r < 1.0 && a <= 1.0 || length(p) > 45.0) {
-        return true;
+    let gt = g * t;
+    let gphi = g * phi;
+    let h = mat2x2(dot(gt, t), dot(gt, phi), dot(gphi, t), dot(gphi, phi));
+    let det = determinant(h);
+    if (abs(det) < 1e-6) {
+        return static_velocity(x);
+    }
+
+    let h_inv = mat2x2(h[1][1], -h[0][1], -h[1][0], h[0][0]) * (1.0 / det);
+    let b = vec2(-disc.isco_energy, disc.isco_angular_momentum);
+    let c = vec2(dot(gt, n), dot(gphi, n));
+    let w = h_inv * b;
+    let d = h_inv * c;
+
+    let gamma = -sqrt(max((-1.0 - dot(b, w)) / (dot(g * n, n) - dot(c, d)), 0.0));
+    let ab = w - gamma * d;
+    return ab.x * t + ab.y * phi + gamma * n;
+}
+
+// Redshift factor g = ν_obs / ν_emit of light emitted by the disc at x and received with energy e_obs by the camera.
+fn redshift(p: vec4<f32>, x: vec4<f32>, e_obs: f32) -> f32 {
+    // Photons are traced backwards from the camera, so the emitted photon travels against the spatial momentum
+    let k = vec4(p.x, -p.yzw);
+    return e_obs / dot(k, disc_velocity(x));
+}
+
+// Fraction of the step from x0 to x1 at which the ray crosses the thin disc, or a negative value if it does not.
+fn thin_disc_crossing(x0: vec4<f32>, x1: vec4<f32>) -> f32 {
+    if (x0.w * x1.w > 0.0 || x0.w == x1.w) {
+        return -1.0;
+    }
+
+    let t = x0.w / (x0.w - x1.w);
+    let r = radius(mix(x0, x1, t).yzw);
+    if (r < max(disc.isco, spacetime.horizon) || r > disc.outer_radius) {
+        return -1.0;
+    }
+
+    return t;
+}
+
+// Radiance emitted by the thin disc at x towards the camera. The disc is optically thick, so it radiates as a black
+// body whose temperature follows the fourth root of the flux.
+fn thin_disc_emission(p: vec4<f32>, x: vec4<f32>, e_obs: f32) -> vec3<f32> {
+    let s = clamp((radius(x.yzw) - disc.isco) / (disc.outer_radius - disc.isco), 0.0, 1.0) * 63.0;
+    let i = min(u32(s), 62u);
+    let flux = mix(disc.flux[i / 4u][i % 4u], disc.flux[(i + 1u) / 4u][(i + 1u) % 4u], s - f32(i));
+
+    var g = 1.0;
+    if (disc.redshift != REDSHIFT_OFF) {
+        g = redshift(p, x, e_obs);
+    }
+
+    return thin_disc_brightness * flux * emission(disc.temperature * pow(flux, 0.25), g);
+}
+
+// Reasons for which a photon path stops being integrated
+const TERMINATION_NONE: u32 = 0u;
+const TERMINATION_ESCAPED: u32 = 1u;
+const TERMINATION_CAPTURED: u32 = 2u;
+const TERMINATION_DIVERGED: u32 = 3u;
+const TERMINATION_STEP_BUDGET: u32 = 4u;
+const TERMINATION_BOUNCE_LIMIT: u32 = 5u;
+const TERMINATION_DISC: u32 = 6u;
+
+// Updates the timestep for the current position and returns why the path should terminate, if it should.
+fn update_dt(p: vec4<f32>, x: vec4<f32>) -> u32 {
+    let cdist = spacetime.lensing_radius;
+    let horizon = spacetime.horizon;
+
+    let r = radius(x.yzw);
+
+    dt = mix(dt_min, dt_max, pow(max(r - horizon, 0.0) / cdist, 1.0));
+
+    if (r < horizon) {
+        return TERMINATION_CAPTURED;
+    }
+
+    // Also catches momenta that are no longer finite
+    if (!(length(p) <= spacetime.max_momentum)) {
+        return TERMINATION_DIVERGED;
     }
 
     if (length(x.yzw) > cdist) {
-        return true;
+        return TERMINATION_ESCAPED;
     }
 
-    return false;
+    return TERMINATION_NONE;
+}
+
+// Value of the Hamiltonian g^{μν} p_μ p_ν, which stays constant along an exactly integrated geodesic.
+fn hamiltonian(p: vec4<f32>, x: vec4<f32>) -> f32 {
+    let ks = kerr_schild(x.yzw);
+    let kp = dot(vec4(-ks.k.x, ks.k.yzw), p);
+    return dot(p, vec4(-p.x, p.yzw)) - ks.f * kp * kp;
 }
 
 fn dhstep(s: mat2x4<f32>, dt: f32) -> mat2x4<f32> {
+    let finite_difference = ((view.flags >> 2u) & 1u) != 0u;
+
     let p = s[0];
     let x = s[1];
 
-    let g = metric(x);
-    let g_inv = inverse(g);
-    let dxdt = g_inv * p;
+    var g_inv: mat4x4<f32>;
+    var dhdq: vec4<f32>;
 
-    let dhdq = -(vec4(lagrangian(dxdt, x + dx.yxxx),
+    if (finite_difference) {
+        let g = metric(x);
+        g_inv = inverse(g);
+        let dxdt = g_inv * p;
+
+        dhdq = -(vec4(lagrangian(dxdt, x + dx.yxxx),
                       lagrangian(dxdt, x + dx.xyxx),
                       lagrangian(dxdt, x + dx.xxyx),
                       lagrangian(dxdt, x + dx.xxxy)) - lagrangian_metric(dxdt, g)) / eps;
+    } else {
+        // Since k is null, the inverse metric is η - f k'⊗k' with k' = ηk.
+        let ks = kerr_schild(x.yzw);
+        let ku = vec4(-ks.k.x, ks.k.yzw);
+        g_inv = diag(vec4(-1.0, 1.0, 1.0, 1.0)) - ks.f * mat4x4(ku.x * ku, ku.y * ku, ku.z * ku, ku.w * ku);
+        let dxdt = g_inv * p;
+
+        // ∂g/∂x_i = ∂f/∂x_i k⊗k + f (∂k/∂x_i⊗k + k⊗∂k/∂x_i)
+        let kv = dot(ks.k, dxdt);
+        dhdq = -vec4(0.0, ks.df * kv * kv + 2.0 * ks.f * kv * (dxdt * ks.dk));
+    }
 
     var dqp: mat2x4<f32>;
 
@@ -364,19 +755,118 @@ fn dhstep(s: mat2x4<f32>, dt: f32) -> mat2x4<f32> {
     return dqp;
 }
 
-@fragment
-fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+fn rk4(s: mat2x4<f32>, h: f32) -> mat2x4<f32> {
+    let k1 = dhstep(s, h);
+    let k2 = dhstep(s + 0.5 * k1, h);
+    let k3 = dhstep(s + 0.5 * k2, h);
+    let k4 = dhstep(s + k3, h);
+    return s + (k1 + 2.0 * k2 + 2.0 * k3 + k4) * (1.0 / 6.0);
+}
+
+// Generalised leapfrog (implicit Störmer-Verlet), which is symplectic and time-reversible for the non-separable
+// geodesic Hamiltonian. The half kick is implicit in the momentum and the drift in the position, both solved by
+// fixed-point iteration.
+fn leapfrog(s: mat2x4<f32>, h: f32) -> mat2x4<f32> {
+    var p_half = s[0];
+    for (var i = 0u; i < leapfrog_iterations; i++) {
+        p_half = s[0] + 0.5 * dhstep(mat2x4(p_half, s[1]), h)[0];
+    }
+
+    let v0 = dhstep(mat2x4(p_half, s[1]), h)[1];
+    var x = s[1] + v0;
+    for (var i = 0u; i < leapfrog_iterations; i++) {
+        x = s[1] + 0.5 * (v0 + dhstep(mat2x4(p_half, x), h)[1]);
+    }
+
+    let p = p_half + 0.5 * dhstep(mat2x4(p_half, x), h)[0];
+    return mat2x4(p, x);
+}
+
+// Dormand-Prince 5(4) step with error control. Rejected steps are retried with a smaller step, and `step_scale` is
+// updated so that the next step starts from the size the error estimate suggests.
+fn dormand_prince(s: mat2x4<f32>, h0: f32) -> mat2x4<f32> {
+    var h = h0;
+
+    for (var i = 0u; i <= max_rejections; i++) {
+        let k1 = dhstep(s, h);
+        let k2 = dhstep(s + k1 * (1.0 / 5.0), h);
+        let k3 = dhstep(s + k1 * (3.0 / 40.0) + k2 * (9.0 / 40.0), h);
+        let k4 = dhstep(s + k1 * (44.0 / 45.0) - k2 * (56.0 / 15.0) + k3 * (32.0 / 9.0), h);
+        let k5 = dhstep(s + k1 * (19372.0 / 6561.0) - k2 * (25360.0 / 2187.0) + k3 * (64448.0 / 6561.0)
+                          - k4 * (212.0 / 729.0), h);
+        let k6 = dhstep(s + k1 * (9017.0 / 3168.0) - k2 * (355.0 / 33.0) + k3 * (46732.0 / 5247.0)
+                          + k4 * (49.0 / 176.0) - k5 * (5103.0 / 18656.0), h);
+        let y = s + k1 * (35.0 / 384.0) + k3 * (500.0 / 1113.0) + k4 * (125.0 / 192.0) - k5 * (2187.0 / 6784.0)
+                  + k6 * (11.0 / 84.0);
+        let k7 = dhstep(y, h);
+
+        // Difference between the fifth and embedded fourth order solutions
+        let e = k1 * (71.0 / 57600.0) - k3 * (71.0 / 16695.0) + k4 * (71.0 / 1920.0) - k5 * (17253.0 / 339200.0)
+                  + k6 * (22.0 / 525.0) - k7 * (1.0 / 40.0);
+        let err = max(abs(e[0]) / (1.0 + abs(y[0])), abs(e[1]) / (1.0 + abs(y[1])));
+        let err_max = max(max(err.x, err.y), max(err.z, err.w)) / integration.tolerance;
+
+        let factor = 0.9 * pow(max(err_max, 1e-10), -0.2);
+
+        if (err_max <= 1.0 || step_scale <= step_scale_min || i == max_rejections) {
+            step_scale = clamp(step_scale * clamp(factor, 0.2, 5.0), step_scale_min, step_scale_max);
+            accepted_step = h;
+            return y;
+        }
+
+        let shrink = max(factor, 0.2);
+        step_scale = max(step_scale * shrink, step_scale_min);
+        h *= shrink;
+    }
+
+    accepted_step = 0.0;
+    return s;
+}
+
+fn integrate(s: mat2x4<f32>, h: f32) -> mat2x4<f32> {
+    accepted_step = h;
+
+    switch (integration.method) {
+        case INTEGRATOR_RK4: {
+            return rk4(s, h);
+        }
+        case INTEGRATOR_DORMAND_PRINCE: {
+            return dormand_prince(s, h);
+        }
+        case INTEGRATOR_LEAPFROG: {
+            return leapfrog(s, h);
+        }
+        default: {
+            return s + dhstep(s, h);
+        }
+    }
+}
+
+struct Trace {
+    // Radiance carried by the photon, with alpha set to one unless the sample was discarded
+    color: vec4<f32>,
+    // Maximum drift of the Hamiltonian from its initial value (only tracked for diagnostics)
+    hamiltonian_drift: f32,
+    steps: u32,
+    termination: u32,
+    // Direction in the frame of the sky texture and weight of the sky radiance added to `color`, if any
+    sky_dir: vec3<f32>,
+    sky_weight: vec3<f32>,
+    samples_sky: bool,
+};
+
+fn trace(in: VertexOutput, diagnostics: bool) -> Trace {
     let render_skybox = (view.flags & 1u) != 0u;
     let render_disc = ((view.flags >> 1u) & 1u) != 0u;
 
     let frag_coord = in.uv * vec2<f32>(view.resolution.xy);
-    rng_state = view.frame_count * view.resolution.x * view.resolution.y + u32(frag_coord.y) * view.resolution.x + u32(frag_coord.x);
+    rng_state = triple32(view.seed) ^ (view.frame_count * view.resolution.x * view.resolution.y + u32(frag_coord.y) * view.resolution.x + u32(frag_coord.x));
     let pos = (2.0 * (frag_coord + rand2() - 0.5) - vec2<f32>(view.resolution.xy)) / f32(view.resolution.y);
 
     let rd = normalize((view.camera * normalize(vec4(pos, view.focal_length, 1.0))).xyz);
     var ro = view.position;
 
-    let t0 = sphere_intersect(ro, rd, vec4(0.0, 0.0, 0.0, cdist));
+    let t0 = sphere_intersect(ro, rd, vec4(0.0, 0.0, 0.0, spacetime.lensing_radius));
     if (t0 > 0.0 && t0 < 1e10) {
         ro += rd * t0;
     }
@@ -384,45 +874,95 @@ fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
     var x = vec4(0.0, ro);
     var p = normalize(null_momentum(rd, x.yzw));
 
-    let p0 = p.x;
+    // Energy of the photon measured by the camera, which is at rest in the Kerr-Schild coordinates
+    let e_obs = dot(p, static_velocity(x));
+
+    var out: Trace;
+    out.hamiltonian_drift = 0.0;
+    out.steps = steps;
+    out.termination = TERMINATION_STEP_BUDGET;
+
+    var h0 = 0.0;
+    if (diagnostics) {
+        h0 = hamiltonian(p, x);
+    }
+
+    let thin_disc = render_disc && disc.model == DISC_MODEL_THIN;
 
     var discard_sample = false;
+    var hit_disc = false;
     var bounces = 0u;
 
     var r = vec3(0.0);
     var att = vec3(1.0);
 
     for (var i = 0u; i < steps; i++) {
-        if (render_disc) {
+        var volume: SampleVolumeOut;
+        if (render_disc && !thin_disc) {
             if (bounces > max_bounces) {
                 discard_sample = true;
+                out.steps = i;
+                out.termination = TERMINATION_BOUNCE_LIMIT;
+                break;
+            }
+
+            // The redshift needs the metric, so it is only computed where the disc can emit
+            var g = 1.0;
+            if (disc.redshift != REDSHIFT_OFF && inside_volume(x.yzw)) {
+                g = redshift(p, x, e_obs);
+            }
+
+            volume = sample_volume(x.yzw, g);
+        }
+
+        let dt1 = clamp(1.0 / length(p), 0.1, 4.0);
+        let state = integrate(mat2x4(p, x), dt1 * dt * step_scale);
+
+        // The thin disc is opaque, so rays end where they cross it
+        if (thin_disc) {
+            let t = thin_disc_crossing(x, state[1]);
+            if (t >= 0.0) {
+                r += att * thin_disc_emission(mix(p, state[0], t), mix(x, state[1], t), e_obs);
+                hit_disc = true;
+                out.steps = i + 1u;
+                out.termination = TERMINATION_DISC;
                 break;
             }
+        }
 
-            let d = sample_volume(x.yzw, p0 / p.x);
-            r += att * d.e * dt;
+        p = state[0];
+        x = state[1];
 
-            if (d.v > 0.0) {
-                let absorb = exp(-1.0 * dt * d.v);
+        // Emission and absorption along the step that was actually taken, scattering at its end
+        if (render_disc && !thin_disc) {
+            let step_length = accepted_step / dt1;
+            r += att * volume.e * step_length;
+
+            if (volume.v > 0.0) {
+                let absorb = exp(-1.0 * step_length * volume.v);
 
                 if (absorb < rand()) {
                     let v = length(p.yzw) * reflect(normalize(p.yzw), udir3());
                     p = vec4(p.x, v.x, v.y, v.z);
-                    att *= d.c;
+                    att *= volume.c;
                     bounces += 1u;
+
+                    // Scattering changes the momentum, so the drift is measured from the new value
+                    if (diagnostics) {
+                        h0 = hamiltonian(p, x);
+                    }
                 }
             }
         }
 
-        let dt1 = clamp(1.0 / length(p), 0.1, 4.0);
-        var state = mat2x4(p, x);
-        let dqp = dhstep(state, dt1 * dt);
-        state += dqp;
-
-        p = state[0];
-        x = state[1];
+        if (diagnostics) {
+            out.hamiltonian_drift = max(out.hamiltonian_drift, abs(hamiltonian(p, x) - h0));
+        }
 
-        if (update_dt(p, x)) {
+        let termination = update_dt(p, x);
+        if (termination != TERMINATION_NONE) {
+            out.steps = i + 1u;
+            out.termination = termination;
             break;
         }
     }
@@ -430,27 +970,172 @@ fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
     let dxdt = dxdt_from_momentum(p, x);
     let out_dir = normalize(dxdt.yzw);
 
-    let p1 = p.x;
-
     var col = vec3(0.0);
 
-    if (length(x.yzw) > 3.0 && !discard_sample) {
-        if (render_skybox) {
-            r += att * textureSample(sky_texture, sky_sampler, out_dir).rgb;
+    if ((radius(x.yzw) > spacetime.photon_orbit || hit_disc) && !discard_sample) {
+        // The sky is sampled by the entry points, where derivatives across neighbouring pixels are available
+        if (render_skybox && !hit_disc) {
+            out.sky_dir = (view.sky_rotation * vec4(out_dir, 0.0)).xyz;
+            out.sky_weight = att * view.sky_intensity;
+            out.samples_sky = true;
         }
         col = r;
     }
 
-    var old_col = vec4(0.0);
-
-    if (view.frame_count != 0u) {
-        let load_coord = vec2(in.uv.x, 1.0 - in.uv.y) * vec2<f32>(view.resolution.xy);
-        old_col = textureLoad(last_frame, vec2<u32>(load_coord), 0);
+    if (discard_sample) {
+        out.color = vec4(0.0);
+    } else {
+        out.color = vec4(col, 1.0);
     }
 
-    if (discard_sample) {
-        return old_col;
+    return out;
+}
+
+// Profile of the galactic band around the equator of the sky, with the bulge at its centre in `+x`
+fn galactic_band(dir: vec3<f32>) -> f32 {
+    return exp(-dir.z * dir.z / 0.015);
+}
+
+fn galaxy(dir: vec3<f32>) -> vec3<f32> {
+    let band = galactic_band(dir);
+    let bulge = exp((dir.x - 1.0) / 0.08) * band;
+    let clouds = fbm(6.0 * dir, 5u);
+    let dust = smoothstep(0.45, 0.7, fbm(12.0 * dir + 3.1, 4u)) * exp(-dir.z * dir.z / 0.002);
+
+    let color = band * (0.4 + 0.9 * clouds) * vec3(0.75, 0.8, 1.0) + 1.5 * bulge * vec3(1.0, 0.85, 0.65);
+    return view.galaxy_brightness * 0.1 * color * (1.0 - 0.8 * dust);
+}
+
+// Uniform random number in [0, 1] from a hash and an index
+fn star_random(hash: u32, i: u32) -> f32 {
+    return f32(triple32(hash + i)) / f32(0xFFFFFFFFu);
+}
+
+// Point stars on a grid of equal angles on the faces of a cube, with at most one star at a random position inside
+// each cell. Fluxes follow the distribution of a uniform population of stars, N(>F) ~ F^-1.5, and the stars are
+// denser in the galactic band.
+fn stars(dir: vec3<f32>) -> vec3<f32> {
+    let a = abs(dir);
+    var face: u32;
+    var uv: vec2<f32>;
+    if (a.x >= a.y && a.x >= a.z) {
+        face = select(1u, 0u, dir.x > 0.0);
+        uv = dir.yz / a.x;
+    } else if (a.y >= a.z) {
+        face = select(3u, 2u, dir.y > 0.0);
+        uv = dir.xz / a.y;
     } else {
-        return vec4(col, 1.0) + old_col;
+        face = select(5u, 4u, dir.z > 0.0);
+        uv = dir.xy / a.z;
+    }
+
+    let grid = f32(view.star_grid);
+    let e = atan(uv) * (4.0 / PI);
+    let cell = min(floor(0.5 * (e + 1.0) * grid), vec2(grid - 1.0));
+    let hash = triple32(view.star_seed ^ triple32((face * view.star_grid + u32(cell.y)) * view.star_grid + u32(cell.x)));
+
+    // Stars stay clear of the cell edges, so that only the cell of the sample needs to be checked
+    let margin = min(4.0 * view.star_size / (0.5 * PI / grid), 0.5);
+    let offset = vec2(margin) + (1.0 - 2.0 * margin) * vec2(star_random(hash, 0u), star_random(hash, 1u));
+    let star_uv = tan((2.0 * (cell + offset) / grid - 1.0) * (PI / 4.0));
+
+    let s = select(-1.0, 1.0, face % 2u == 0u);
+    var star = vec3(s, star_uv);
+    if (face / 2u == 1u) {
+        star = vec3(star_uv.x, s, star_uv.y);
+    } else if (face / 2u == 2u) {
+        star = vec3(star_uv, s);
+    }
+    star = normalize(star);
+
+    if (star_random(hash, 2u) >= view.star_probability * (1.0 + 2.0 * galactic_band(star))) {
+        return vec3(0.0);
+    }
+
+    let flux = view.star_flux * pow(max(star_random(hash, 3u), 1e-3), -2.0 / 3.0);
+    let temperature = 2500.0 * pow(12.0, pow(star_random(hash, 4u), 1.5));
+    let color = xyz2rgb(blackbody(temperature));
+
+    let d = dir - star;
+    let variance = view.star_size * view.star_size;
+    let profile = exp(-0.5 * dot(d, d) / variance) / (TAU * variance);
+    return flux * profile * color / max(max(color.r, color.g), color.b);
+}
+
+// Radiance of a procedural sky of stars and a galactic band in direction `dir`
+fn procedural_sky(dir: vec3<f32>) -> vec3<f32> {
+    return stars(dir) + galaxy(dir);
+}
+
+// Radiance of the sky seen by a trace. With footprint filtering the mip level is selected from the divergence of the
+// escape directions of neighbouring pixels, which covers the magnification by lensing, ignoring neighbours whose
+// rays did not escape. Must be called in uniform control flow.
+fn sky_radiance(t: Trace) -> vec3<f32> {
+    let valid = select(0.0, 1.0, t.samples_sky);
+    var dx = dpdxFine(t.sky_dir);
+    var dy = dpdyFine(t.sky_dir);
+    if (dpdxFine(valid) != 0.0) {
+        dx = vec3(0.0);
+    }
+    if (dpdyFine(valid) != 0.0) {
+        dy = vec3(0.0);
+    }
+
+    if (!t.samples_sky) {
+        return vec3(0.0);
+    }
+
+    if ((view.flags & 16u) != 0u) {
+        return t.sky_weight * procedural_sky(t.sky_dir);
+    }
+
+    var lod = 0.0;
+    if ((view.flags & 8u) != 0u) {
+        lod = sky_lod(max(length(dx), length(dy)), textureDimensions(sky_texture).x);
+    }
+
+    return t.sky_weight * textureSampleLevel(sky_texture, sky_sampler, t.sky_dir, lod).rgb;
+}
+
+// Mip level at which a texel of a cubemap face of `size` texels covers an angle of `footprint` radians, using the
+// texel size at the centre of the face
+fn sky_lod(footprint: f32, size: u32) -> f32 {
+    return max(log2(0.5 * footprint * f32(size)), 0.0);
+}
+
+fn load_last(texture: texture_2d<f32>, uv: vec2<f32>) -> vec4<f32> {
+    if (view.frame_count == 0u) {
+        return vec4(0.0);
     }
+
+    let load_coord = vec2(uv.x, 1.0 - uv.y) * vec2<f32>(view.resolution.xy);
+    return textureLoad(texture, vec2<u32>(load_coord), 0);
+}
+
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+    let t = trace(in, false);
+    return t.color + vec4(sky_radiance(t), 0.0) + load_last(last_frame, in.uv);
+}
+
+struct DiagnosticsOutput {
+    @location(0) color: vec4<f32>,
+    // Maximum Hamiltonian drift, summed step count, termination reason of the latest sample and sample count
+    @location(1) diagnostics: vec4<f32>,
+};
+
+@fragment
+fn fragment_diagnostics(in: VertexOutput) -> DiagnosticsOutput {
+    let t = trace(in, true);
+    let old = load_last(last_diagnostics, in.uv);
+
+    var out: DiagnosticsOutput;
+    out.color = t.color + vec4(sky_radiance(t), 0.0) + load_last(last_frame, in.uv);
+    out.diagnostics = vec4(
+        max(old.x, t.hamiltonian_drift),
+        old.y + f32(t.steps),
+        f32(t.termination),
+        old.w + 1.0,
+    );
+    return out;
 }
@@ -1,160 +1,781 @@
+use std::f32::consts::{FRAC_PI_2, PI};
 use std::mem;
+use std::path::Path;
 
-use glam::{Mat4, Vec3};
+use bytemuck::Zeroable;
+use glam::{Mat3, Mat4, Vec3};
+use image::Rgba32FImage;
 
-use crate::state::State;
+use crate::black_hole::BlackHole;
+use crate::cpu::{geodesic, CubeMap, Tracer};
+use crate::diagnostics::Diagnostics;
+use crate::disc::{self, DiscModel, Redshift};
+use crate::error::Error;
+use crate::geometry::Geometry;
+use crate::integrator::Integrator;
+use crate::metric::{Gradient, Metric};
+use crate::output;
+use crate::shadow::Shadow;
+use crate::sky::{self, ProceduralSky, SkyFilter};
+use crate::state::{ErrorScope, State};
+use crate::types::{Disc, Integration, Spacetime, Uniforms, View};
 
+enum Backend {
+    Gpu(Box<State>),
+    Cpu(Box<Tracer>),
+}
+
+/// Where a [`Renderer`] traces rays.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Device {
+    /// The GPU, falling back to the CPU if it cannot be used.
+    #[default]
+    Auto,
+    /// The GPU, failing if it cannot be used.
+    Gpu,
+    /// The CPU reference implementation, which needs no GPU but is much slower.
+    Cpu,
+}
+
+/// Traces images of a black hole, accumulating one sample per pixel in every frame.
+///
+/// The renderer can be reused: after changing any setting, the next frame restarts the accumulation.
 pub struct Renderer {
     width: u32,
     height: u32,
-    state: State,
+    backend: Backend,
     target: Vec<u8>,
     frames: u32,
     frame_count: usize,
+    uniforms: Uniforms,
+    // Settings of the frames in the accumulated image, with a frame count of zero
+    accumulated: Option<Uniforms>,
+    black_hole: BlackHole,
+    metric: Metric,
+    allow_naked_singularities: bool,
+    diagnostics: bool,
+    diagnostics_data: Vec<u8>,
+    shadow_overlay: bool,
+    // Why the GPU could not be used by `Device::Auto`
+    fallback: Option<Error>,
 }
 
 impl Renderer {
-    pub fn new(width: u32, height: u32) -> Self {
+    /// Creates a renderer on the GPU, failing if no suitable adapter is available or the device cannot create the
+    /// render pipeline.
+    pub fn new(width: u32, height: u32) -> Result<Self, Error> {
+        validate_size(width, height)?;
+
         let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::default());
         let target = Vec::<u8>::with_capacity((width * height * 4) as usize * mem::size_of::<f32>());
+        let state = pollster::block_on(State::new(&instance, width, height))?;
+
+        Ok(Self::with_backend(width, height, Backend::Gpu(Box::new(state)), target))
+    }
+
+    /// Creates a renderer on the CPU reference implementation, which needs no GPU but is much slower.
+    pub fn new_cpu(width: u32, height: u32) -> Result<Self, Error> {
+        validate_size(width, height)?;
+
+        let target = Vec::<u8>::with_capacity((width * height * 4) as usize * mem::size_of::<f32>());
+        let tracer = Tracer::new(width, height, CubeMap::new(sky::default_faces()?));
+
+        Ok(Self::with_backend(
+            width,
+            height,
+            Backend::Cpu(Box::new(tracer)),
+            target,
+        ))
+    }
+
+    /// Creates a renderer on `device`. If [`Device::Auto`] falls back to the CPU, the reason is kept in
+    /// [`Renderer::fallback_reason`].
+    pub fn with_device(width: u32, height: u32, device: Device) -> Result<Self, Error> {
+        match device {
+            Device::Auto => Self::new(width, height).or_else(|e| {
+                let mut renderer = Self::new_cpu(width, height)?;
+                renderer.fallback = Some(e);
+                Ok(renderer)
+            }),
+            Device::Gpu => Self::new(width, height),
+            Device::Cpu => Self::new_cpu(width, height),
+        }
+    }
 
-        Self {
+    fn with_backend(width: u32, height: u32, backend: Backend, target: Vec<u8>) -> Self {
+        let uniforms = Uniforms {
+            view: View {
+                resolution: [width, height],
+                camera: Mat4::IDENTITY.to_cols_array(),
+                focal_length: 1.5,
+                sky_intensity: 1.0,
+                sky_rotation: Mat4::IDENTITY.to_cols_array(),
+                ..View::zeroed()
+            },
+            spacetime: Spacetime {
+                lensing_radius: 120.0,
+                ..Spacetime::zeroed()
+            },
+            integration: Integration {
+                method: Integrator::default() as u32,
+                tolerance: 1e-4,
+                ..Integration::zeroed()
+            },
+            disc: Disc {
+                redshift: Redshift::default() as u32,
+                model: DiscModel::default() as u32,
+                outer_radius: DiscModel::default().outer_radius(),
+                temperature: 8000.0,
+                ..Disc::zeroed()
+            },
+        };
+
+        let mut renderer = Self {
             width,
             height,
-            state: pollster::block_on(State::new(&instance, &target, width, height)),
+            backend,
             target,
             frames: 1,
             frame_count: 0,
-        }
+            uniforms,
+            accumulated: None,
+            black_hole: BlackHole::default(),
+            metric: Metric::default(),
+            allow_naked_singularities: false,
+            diagnostics: false,
+            diagnostics_data: Vec::new(),
+            shadow_overlay: false,
+            fallback: None,
+        };
+
+        renderer.update_spacetime();
+        renderer
     }
 
     pub fn set_view(&mut self, camera: Mat4, position: Vec3, focal_length: f32) {
-        self.state.view.camera = camera.to_cols_array();
-        self.state.view.position = position.to_array();
-        self.state.view.focal_length = focal_length;
+        self.uniforms.view.camera = camera.to_cols_array();
+        self.uniforms.view.position = position.to_array();
+        self.uniforms.view.focal_length = focal_length;
+    }
+
+    /// Sets the mass `M`, spin `a = J/M` and charge `Q` of the black hole.
+    ///
+    /// Parameters with `a² + Q² > M²` are rejected unless naked singularities have been allowed with
+    /// [`Renderer::set_allow_naked_singularities`]. Spin and charge are ignored by metrics that do not support them.
+    pub fn set_black_hole(&mut self, mass: f32, spin: f32, charge: f32) -> Result<(), Error> {
+        let black_hole = BlackHole::new(mass, spin, charge);
+        black_hole
+            .restrict_to(self.metric)
+            .validate(self.allow_naked_singularities)?;
+
+        self.black_hole = black_hole;
+        self.update_spacetime();
+
+        Ok(())
+    }
+
+    pub fn set_metric(&mut self, metric: Metric) -> Result<(), Error> {
+        self.black_hole
+            .restrict_to(metric)
+            .validate(self.allow_naked_singularities)?;
+
+        self.metric = metric;
+        self.update_spacetime();
+
+        Ok(())
+    }
+
+    /// Sets the metric, the black hole and whether naked singularities are allowed at once, validating only the new
+    /// combination. Unlike calling the individual setters in turn, this cannot fail on the parameters being replaced.
+    pub fn set_spacetime(
+        &mut self,
+        metric: Metric,
+        mass: f32,
+        spin: f32,
+        charge: f32,
+        allow_naked_singularities: bool,
+    ) -> Result<(), Error> {
+        let black_hole = BlackHole::new(mass, spin, charge);
+        black_hole.restrict_to(metric).validate(allow_naked_singularities)?;
+
+        self.metric = metric;
+        self.black_hole = black_hole;
+        self.allow_naked_singularities = allow_naked_singularities;
+        self.update_spacetime();
 
-        self.state
-            .queue
-            .write_buffer(&self.state.view_buffer, 0, bytemuck::cast_slice(&[self.state.view]));
+        Ok(())
+    }
+
+    /// Allows black hole parameters with `a² + Q² > M²`. Disallowing them fails, and keeps them allowed, while such
+    /// a black hole is set.
+    pub fn set_allow_naked_singularities(&mut self, v: bool) -> Result<(), Error> {
+        self.black_hole.restrict_to(self.metric).validate(v)?;
+        self.allow_naked_singularities = v;
+
+        Ok(())
+    }
+
+    /// Sets the radius of the sphere of influence in which gravitational lensing occurs.
+    pub fn set_lensing_radius(&mut self, radius: f32) -> Result<(), Error> {
+        if !radius.is_finite() || radius <= 0.0 {
+            return Err(Error::InvalidParameter {
+                name: "lensing radius",
+                value: radius,
+            });
+        }
+
+        self.uniforms.spacetime.lensing_radius = radius;
+        self.update_spacetime();
+
+        Ok(())
+    }
+
+    fn update_spacetime(&mut self) {
+        let black_hole = self.black_hole.restrict_to(self.metric);
+
+        self.uniforms.spacetime.mass = black_hole.mass;
+        self.uniforms.spacetime.spin = black_hole.spin;
+        self.uniforms.spacetime.charge = black_hole.charge;
+        self.uniforms.spacetime.metric = self.metric as u32;
+        let geometry = Geometry::new(&black_hole);
+        self.uniforms.spacetime.horizon = geometry.outer_horizon.unwrap_or(0.0);
+        self.uniforms.spacetime.photon_orbit = geometry.photon_orbit_prograde.unwrap_or(0.0);
+        self.uniforms.spacetime.max_momentum = geodesic::divergence_momentum(&self.uniforms.spacetime);
+
+        // The disc rotates in the direction of positive φ, against the black hole if the spin is negative. Without a
+        // stable circular orbit the whole disc is treated as orbiting.
+        let isco = if black_hole.spin >= 0.0 {
+            geometry.isco_prograde
+        } else {
+            geometry.isco_retrograde
+        };
+        let isco = isco.unwrap_or(0.0);
+        let (energy, angular_momentum) = black_hole.circular_orbit(isco).unwrap_or((1.0, 0.0));
+
+        self.uniforms.disc.isco = isco;
+        self.uniforms.disc.isco_energy = energy;
+        self.uniforms.disc.isco_angular_momentum = angular_momentum;
+        self.uniforms.disc.flux = disc::novikov_thorne_flux(&black_hole, isco, disc::DISC_RADIUS);
+    }
+
+    /// Replaces the skybox with cubemap faces in the order right, left, top, bottom, front, back. The faces must be
+    /// square and of equal size.
+    pub fn set_skybox(&mut self, faces: [Rgba32FImage; 6]) -> Result<(), Error> {
+        sky::validate_faces(&faces)?;
+
+        match &mut self.backend {
+            Backend::Gpu(state) => state.set_sky(&faces)?,
+            Backend::Cpu(tracer) => tracer.set_sky(CubeMap::new(faces)),
+        }
+        self.reset_accumulation();
+
+        Ok(())
+    }
+
+    /// Replaces the skybox with the faces in `dir`, see [`sky::load_faces`]. The embedded skybox is used until a
+    /// skybox is set.
+    pub fn set_skybox_from_dir(&mut self, dir: impl AsRef<Path>) -> Result<(), Error> {
+        self.set_skybox(sky::load_faces(dir.as_ref())?)
+    }
+
+    /// Replaces the skybox with an equirectangular panorama, see [`sky::faces_from_equirectangular`].
+    pub fn set_skybox_equirectangular(&mut self, image: &Rgba32FImage) -> Result<(), Error> {
+        self.set_skybox(sky::faces_from_equirectangular(image)?)
+    }
+
+    /// Replaces the skybox with the faces in a directory or the equirectangular panorama in a file, see
+    /// [`sky::load`].
+    pub fn set_skybox_from_path(&mut self, path: impl AsRef<Path>) -> Result<(), Error> {
+        self.set_skybox(sky::load(path.as_ref())?)
+    }
+
+    /// Sets the factor by which the radiance of the sky is multiplied, e.g. to bring an HDR panorama in range.
+    pub fn set_sky_intensity(&mut self, intensity: f32) -> Result<(), Error> {
+        if !intensity.is_finite() || intensity < 0.0 {
+            return Err(Error::InvalidParameter {
+                name: "sky intensity",
+                value: intensity,
+            });
+        }
+
+        self.uniforms.view.sky_intensity = intensity;
+
+        Ok(())
+    }
+
+    /// Replaces the skybox with a procedural sky and enables rendering it, or restores the skybox if `None`.
+    pub fn set_procedural_sky(&mut self, sky: Option<ProceduralSky>) -> Result<(), Error> {
+        let Some(sky) = sky else {
+            self.uniforms.view.flags &= !0b10000;
+            return Ok(());
+        };
+
+        for (name, value) in [
+            ("star density", sky.density),
+            ("star brightness", sky.brightness),
+            ("galaxy brightness", sky.galaxy),
+        ] {
+            if !value.is_finite() || value < 0.0 {
+                return Err(Error::InvalidParameter { name, value });
+            }
+        }
+
+        // A face of the cube covers a sixth of the sky, about 6876 square degrees. Its cells are about equally large, as
+        // they are spaced evenly in angle, and there is about one star in every fourth one.
+        let face_area = 4.0 * PI * (180.0 / PI).powi(2) / 6.0;
+        let stars = sky.density * face_area;
+        let grid = (stars / 0.25).sqrt().ceil().clamp(1.0, 8192.0);
+        let arcminute = (1.0f32 / 60.0).to_radians();
+        let view = &mut self.uniforms.view;
+
+        view.star_seed = sky.seed;
+        view.star_grid = grid as u32;
+        view.star_probability = (stars / (grid * grid)).min(1.0);
+        view.star_flux = sky.brightness * arcminute * arcminute;
+        view.star_size = arcminute.min(0.125 * FRAC_PI_2 / grid);
+        view.galaxy_brightness = sky.galaxy;
+        view.flags |= 0b10001;
+
+        Ok(())
+    }
+
+    pub fn set_sky_filter(&mut self, filter: SkyFilter) {
+        if filter == SkyFilter::Footprint {
+            self.uniforms.view.flags |= 0b1000;
+        } else {
+            self.uniforms.view.flags &= !0b1000;
+        }
+    }
+
+    /// Sets the orientation of the sky, which is rotated by `rotation` from its default orientation.
+    pub fn set_sky_rotation(&mut self, rotation: Mat3) {
+        self.uniforms.view.sky_rotation = Mat4::from_mat3(rotation.transpose()).to_cols_array();
     }
 
     pub fn set_render_skybox(&mut self, v: bool) {
         if v {
-            self.state.view.flags |= 1;
+            self.uniforms.view.flags |= 1;
         } else {
-            self.state.view.flags &= !1;
+            self.uniforms.view.flags &= !1;
         }
     }
 
     pub fn set_render_disc(&mut self, v: bool) {
         if v {
-            self.state.view.flags |= 0b10;
+            self.uniforms.view.flags |= 0b10;
+        } else {
+            self.uniforms.view.flags &= !0b10;
+        }
+    }
+
+    /// Sets how the gravitational and Doppler shift of the light emitted by the accretion disc is rendered.
+    pub fn set_redshift(&mut self, redshift: Redshift) {
+        self.uniforms.disc.redshift = redshift as u32;
+    }
+
+    pub fn set_disc_model(&mut self, model: DiscModel) {
+        self.uniforms.disc.model = model as u32;
+        self.uniforms.disc.outer_radius = model.outer_radius();
+    }
+
+    /// Sets the peak temperature of the thin accretion disc in Kelvin, as seen by gas orbiting with it.
+    pub fn set_disc_temperature(&mut self, temperature: f32) -> Result<(), Error> {
+        if !temperature.is_finite() || temperature <= 0.0 {
+            return Err(Error::InvalidParameter {
+                name: "disc temperature",
+                value: temperature,
+            });
+        }
+
+        self.uniforms.disc.temperature = temperature;
+
+        Ok(())
+    }
+
+    pub fn set_gradient(&mut self, gradient: Gradient) {
+        if gradient == Gradient::FiniteDifference {
+            self.uniforms.view.flags |= 0b100;
         } else {
-            self.state.view.flags &= !0b10;
+            self.uniforms.view.flags &= !0b100;
+        }
+    }
+
+    pub fn set_integrator(&mut self, integrator: Integrator) {
+        self.uniforms.integration.method = integrator as u32;
+    }
+
+    /// Sets the local error tolerance of adaptive integrators, relative to the magnitude of the photon state.
+    pub fn set_tolerance(&mut self, tolerance: f32) -> Result<(), Error> {
+        if !tolerance.is_finite() || tolerance <= 0.0 {
+            return Err(Error::InvalidParameter {
+                name: "tolerance",
+                value: tolerance,
+            });
+        }
+
+        self.uniforms.integration.tolerance = tolerance;
+
+        Ok(())
+    }
+
+    /// Enables recording of per-pixel integration diagnostics, which can be read back with
+    /// [`Renderer::diagnostics`] after rendering. Changing it restarts the accumulation, so that the diagnostics cover
+    /// every frame of the image.
+    pub fn set_diagnostics(&mut self, v: bool) {
+        if v != self.diagnostics {
+            self.reset_accumulation();
+        }
+        self.diagnostics = v;
+    }
+
+    /// Enables the comparison of the rendered shadow with the analytic prediction for the current black hole and
+    /// view, which can be read back with [`Renderer::shadow`] after rendering.
+    ///
+    /// This records diagnostics to find the pixels captured by the black hole, even if the diagnostic mode is
+    /// disabled. Changing it restarts the accumulation like [`Renderer::set_diagnostics`].
+    pub fn set_shadow_overlay(&mut self, v: bool) {
+        if v != self.shadow_overlay {
+            self.reset_accumulation();
         }
+        self.shadow_overlay = v;
+    }
+
+    /// Sets the seed of the random numbers used for sampling, so that renders with different seeds can be averaged.
+    pub fn set_seed(&mut self, seed: u32) {
+        self.uniforms.view.seed = seed;
     }
 
     pub fn set_frames(&mut self, frames: u32) {
         self.frames = frames;
     }
 
-    pub fn render(&mut self) {
+    /// Renders all frames, adding them to the accumulated image, and reads it back.
+    ///
+    /// Calling this again renders more samples of the same image, unless a setting changed in between or
+    /// [`Renderer::reset_accumulation`] was called. Fails if the GPU rejects a command, runs out of memory or is
+    /// lost, after which the renderer should be discarded.
+    pub fn render(&mut self) -> Result<(), Error> {
         for _ in 0..self.frames {
-            self.state.view.frame_count = self.frame_count as u32;
-            self.state
-                .queue
-                .write_buffer(&self.state.view_buffer, 0, bytemuck::cast_slice(&[self.state.view]));
+            self.render_frame()?;
+        }
+
+        match &self.backend {
+            Backend::Gpu(state) => {
+                self.target = state.read_texture(&state.last_frame_textures[0], &state.output_staging_buffer)?;
+
+                if self.diagnostics || self.shadow_overlay {
+                    if let Some(diagnostics) = &state.diagnostics {
+                        self.diagnostics_data =
+                            state.read_texture(&diagnostics.textures[0], &diagnostics.staging_buffer)?;
+                    }
+                }
+            }
+            Backend::Cpu(tracer) => {
+                self.target = tracer.target();
 
-            self.render_frame();
+                if self.diagnostics || self.shadow_overlay {
+                    self.diagnostics_data = tracer.diagnostics();
+                }
+            }
         }
 
-        let mut encoder = self
-            .state
-            .device
-            .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        Ok(())
+    }
 
-        encoder.copy_texture_to_buffer(
-            wgpu::TexelCopyTextureInfo {
-                texture: &self.state.last_frame_textures[0],
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-                aspect: wgpu::TextureAspect::All,
-            },
-            wgpu::TexelCopyBufferInfo {
-                buffer: &self.state.output_staging_buffer,
-                layout: wgpu::TexelCopyBufferLayout {
-                    offset: 0,
-                    bytes_per_row: Some(self.width * 4 * mem::size_of::<f32>() as u32),
-                    rows_per_image: Some(self.height),
-                },
-            },
-            wgpu::Extent3d {
-                width: self.width,
-                height: self.height,
-                depth_or_array_layers: 1,
-            },
-        );
+    /// Renders one frame, restarting the accumulation if a setting changed since the previous one. The image is
+    /// only read back by [`Renderer::render`].
+    pub fn render_frame(&mut self) -> Result<(), Error> {
+        let mut settings = self.uniforms;
+        settings.view.frame_count = 0;
+        if self.accumulated != Some(settings) {
+            self.reset_accumulation();
+            self.accumulated = Some(settings);
+        }
+
+        self.uniforms.view.frame_count = self.frame_count as u32;
+        let diagnostics = self.diagnostics || self.shadow_overlay;
+
+        match &mut self.backend {
+            Backend::Gpu(state) => {
+                if diagnostics {
+                    state.create_diagnostics_targets()?;
+                }
+                render_frame_gpu(state, &self.uniforms, self.frame_count, diagnostics)?;
+            }
+            Backend::Cpu(tracer) => tracer.render_frame(&self.uniforms, diagnostics),
+        }
+
+        self.frame_count += 1;
 
-        self.state.queue.submit(std::iter::once(encoder.finish()));
+        Ok(())
+    }
 
-        let buffer_slice = self.state.output_staging_buffer.slice(..);
-        let (sender, receiver) = flume::bounded(1);
-        buffer_slice.map_async(wgpu::MapMode::Read, move |r| sender.send(r).unwrap());
-        self.state.device.poll(wgpu::Maintain::wait()).panic_on_timeout();
-        pollster::block_on(receiver.recv_async()).unwrap().unwrap();
+    /// Discards the accumulated image and diagnostics, so that the next frame starts a new image.
+    pub fn reset_accumulation(&mut self) {
+        self.frame_count = 0;
+        self.accumulated = None;
+        self.target.clear();
+        self.diagnostics_data.clear();
+    }
 
-        {
-            let view = buffer_slice.get_mapped_range();
-            self.target.extend_from_slice(&view[..]);
+    /// Number of frames in the accumulated image.
+    pub fn accumulated_frames(&self) -> u32 {
+        self.frame_count as u32
+    }
+
+    /// Device the rays are traced on, either [`Device::Gpu`] or [`Device::Cpu`].
+    pub fn device(&self) -> Device {
+        match self.backend {
+            Backend::Gpu(_) => Device::Gpu,
+            Backend::Cpu(_) => Device::Cpu,
         }
+    }
 
-        self.state.output_staging_buffer.unmap();
+    /// Why [`Device::Auto`] fell back to the CPU, if it did.
+    pub fn fallback_reason(&self) -> Option<&Error> {
+        self.fallback.as_ref()
     }
 
-    pub fn render_frame(&mut self) {
-        let mut encoder = self
-            .state
-            .device
-            .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+    /// Diagnostics recorded by the last call to [`Renderer::render`], if the diagnostic mode is enabled.
+    pub fn diagnostics(&self) -> Option<Diagnostics> {
+        if !self.diagnostics {
+            return None;
+        }
 
-        let current_frame_view = &self.state.last_frame_views[self.frame_count % 2];
+        self.recorded_diagnostics()
+    }
 
-        {
-            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("render_pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: current_frame_view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
-                        store: wgpu::StoreOp::Store,
-                    },
-                })],
-                depth_stencil_attachment: None,
-                timestamp_writes: None,
-                occlusion_query_set: None,
-            });
+    /// Predicted shadow and its comparison with the last call to [`Renderer::render`], if the shadow overlay is
+    /// enabled and the black hole has a shadow.
+    pub fn shadow(&self) -> Option<Shadow> {
+        if !self.shadow_overlay {
+            return None;
+        }
 
-            pass.set_pipeline(&self.state.render_pipeline);
-            pass.set_bind_group(0, &self.state.last_frame_bind_groups[(self.frame_count + 1) % 2], &[]);
-            pass.set_bind_group(1, &self.state.view_bind_group, &[]);
-            pass.set_bind_group(2, &self.state.sky_bind_group, &[]);
-            pass.draw(0..3, 0..1);
+        let diagnostics = self.recorded_diagnostics()?;
+        Shadow::new(
+            &self.black_hole.restrict_to(self.metric),
+            &self.uniforms.view,
+            &diagnostics,
+        )
+    }
+
+    fn recorded_diagnostics(&self) -> Option<Diagnostics> {
+        if self.diagnostics_data.is_empty() {
+            return None;
         }
 
+        let data: Vec<_> = self
+            .diagnostics_data
+            .chunks(4)
+            .map(|bytes| {
+                let b = <[u8; 4]>::try_from(bytes).unwrap();
+                f32::from_ne_bytes(b)
+            })
+            .collect();
+
+        Some(Diagnostics::from_raw(self.width, self.height, &data))
+    }
+
+    /// Raw accumulated image read back by the last call to [`Renderer::render`], bottom row first, with the sum
+    /// of the samples in the colour channels and their count in alpha. Empty before the first render.
+    pub fn target(&self) -> &[u8] {
+        &self.target
+    }
+
+    /// Accumulated linear radiance divided by the sample count of each pixel, top row first and with an alpha of
+    /// one. Black before the first render.
+    pub fn image(&self) -> Rgba32FImage {
+        if self.target.is_empty() {
+            return Rgba32FImage::new(self.width, self.height);
+        }
+
+        let pixels = output::linear_pixels(&self.target);
+        Rgba32FImage::from_fn(self.width, self.height, |x, y| {
+            let pixel = pixels[((self.height - 1 - y) * self.width + x) as usize];
+            image::Rgba(pixel.extend(1.0).to_array())
+        })
+    }
+}
+
+/// Rejects empty images, which have no render target.
+fn validate_size(width: u32, height: u32) -> Result<(), Error> {
+    for (name, value) in [("width", width), ("height", height)] {
+        if value == 0 {
+            return Err(Error::InvalidParameter { name, value: 0.0 });
+        }
+    }
+
+    Ok(())
+}
+
+fn render_frame_gpu(state: &State, uniforms: &Uniforms, frame_count: usize, diagnostics: bool) -> Result<(), Error> {
+    let scope = ErrorScope::new(&state.device);
+
+    state
+        .queue
+        .write_buffer(&state.view_buffer, 0, bytemuck::cast_slice(&[uniforms.view]));
+    state
+        .queue
+        .write_buffer(&state.spacetime_buffer, 0, bytemuck::cast_slice(&[uniforms.spacetime]));
+    state.queue.write_buffer(
+        &state.integration_buffer,
+        0,
+        bytemuck::cast_slice(&[uniforms.integration]),
+    );
+    state
+        .queue
+        .write_buffer(&state.disc_buffer, 0, bytemuck::cast_slice(&[uniforms.disc]));
+
+    let mut encoder = state
+        .device
+        .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+
+    let current_frame_view = &state.last_frame_views[frame_count % 2];
+    let diagnostics = state.diagnostics.as_ref().filter(|_| diagnostics);
+
+    {
+        let mut color_attachments = vec![Some(wgpu::RenderPassColorAttachment {
+            view: current_frame_view,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                store: wgpu::StoreOp::Store,
+            },
+        })];
+
+        if let Some(diagnostics) = diagnostics {
+            color_attachments.push(Some(wgpu::RenderPassColorAttachment {
+                view: &diagnostics.views[frame_count % 2],
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            }));
+        }
+
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("render_pass"),
+            color_attachments: &color_attachments,
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+
+        if let Some(diagnostics) = diagnostics {
+            pass.set_pipeline(&state.diagnostics_pipeline);
+            pass.set_bind_group(0, &diagnostics.bind_groups[(frame_count + 1) % 2], &[]);
+        } else {
+            pass.set_pipeline(&state.render_pipeline);
+            pass.set_bind_group(0, &state.last_frame_bind_groups[(frame_count + 1) % 2], &[]);
+        }
+
+        pass.set_bind_group(1, &state.view_bind_group, &[]);
+        pass.set_bind_group(2, &state.sky_bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+
+    encoder.copy_texture_to_texture(
+        state.last_frame_textures[frame_count % 2].as_image_copy(),
+        state.last_frame_textures[(frame_count + 1) % 2].as_image_copy(),
+        state.last_frame_textures[0].size(),
+    );
+
+    if let Some(diagnostics) = diagnostics {
         encoder.copy_texture_to_texture(
-            self.state.last_frame_textures[self.frame_count % 2].as_image_copy(),
-            self.state.last_frame_textures[(self.frame_count + 1) % 2].as_image_copy(),
-            self.state.last_frame_textures[0].size(),
+            diagnostics.textures[frame_count % 2].as_image_copy(),
+            diagnostics.textures[(frame_count + 1) % 2].as_image_copy(),
+            diagnostics.textures[0].size(),
         );
+    }
 
-        self.state.queue.submit(std::iter::once(encoder.finish()));
-        self.state.device.poll(wgpu::Maintain::wait()).panic_on_timeout();
+    state.queue.submit(std::iter::once(encoder.finish()));
+    state.device.poll(wgpu::Maintain::wait());
+    state.check_device()?;
 
-        self.frame_count += 1;
+    match pollster::block_on(scope.finish()) {
+        Some(error) => Err(Error::Gpu(error)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_counts(renderer: &Renderer) -> Vec<f32> {
+        renderer
+            .target()
+            .chunks(16)
+            .map(|pixel| f32::from_ne_bytes(pixel[12..].try_into().unwrap()))
+            .collect()
     }
 
-    pub fn target(self) -> Vec<u8> {
-        self.target
+    #[test]
+    fn disallowing_naked_singularities_revalidates_the_black_hole() {
+        let mut renderer = Renderer::new_cpu(2, 2).unwrap();
+        renderer.set_allow_naked_singularities(true).unwrap();
+        renderer.set_black_hole(1.0, 0.9, 0.9).unwrap();
+
+        assert!(renderer.set_allow_naked_singularities(false).is_err());
+        assert!(renderer.set_black_hole(1.0, 0.9, 1.0).is_ok());
+
+        renderer.set_black_hole(1.0, 0.5, 0.2).unwrap();
+        assert!(renderer.set_allow_naked_singularities(false).is_ok());
+    }
+
+    #[test]
+    fn procedural_sky_has_the_requested_star_density() {
+        let mut renderer = Renderer::new_cpu(2, 2).unwrap();
+        renderer.set_render_skybox(false);
+        let sky = ProceduralSky {
+            density: 2.0,
+            ..ProceduralSky::default()
+        };
+        renderer.set_procedural_sky(Some(sky)).unwrap();
+
+        let view = &renderer.uniforms.view;
+        assert_eq!(view.flags & 0b10001, 0b10001);
+
+        // Away from the galactic band, over the 41253 square degrees of the sky
+        let stars = 6.0 * (view.star_grid as f32).powi(2) * view.star_probability;
+        assert!((stars / 41253.0 - 2.0).abs() < 1e-3, "{stars}");
+    }
+
+    #[test]
+    fn renders_repeatedly_and_restarts_after_changes() {
+        let mut renderer = Renderer::new_cpu(8, 4).unwrap();
+        assert_eq!(renderer.image().get_pixel(0, 0).0, [0.0; 4]);
+
+        renderer.render().unwrap();
+        let first = renderer.image();
+        assert!(first.pixels().flat_map(|pixel| pixel.0).all(f32::is_finite));
+        renderer.render().unwrap();
+        assert_eq!(renderer.accumulated_frames(), 2);
+        assert!(sample_counts(&renderer).iter().all(|&n| n == 2.0));
+        assert_eq!(renderer.image().dimensions(), first.dimensions());
+
+        renderer.set_view(Mat4::IDENTITY, Vec3::new(0.0, -20.0, 1.0), 1.5);
+        renderer.render().unwrap();
+        assert_eq!(renderer.accumulated_frames(), 1);
+        assert_eq!(renderer.target().len(), 8 * 4 * 16);
+        assert!(sample_counts(&renderer).iter().all(|&n| n == 1.0));
+
+        // Diagnostics have to cover every frame of the image
+        renderer.set_diagnostics(true);
+        renderer.render().unwrap();
+        assert_eq!(renderer.accumulated_frames(), 1);
+        renderer.set_diagnostics(true);
+        renderer.render().unwrap();
+        assert_eq!(renderer.accumulated_frames(), 2);
+        renderer.set_shadow_overlay(true);
+        renderer.render().unwrap();
+        assert_eq!(renderer.accumulated_frames(), 1);
+        assert!(renderer.image().pixels().flat_map(|pixel| pixel.0).all(f32::is_finite));
+
+        renderer.reset_accumulation();
+        assert!(renderer.target().is_empty());
+        assert_eq!(renderer.accumulated_frames(), 0);
     }
 }
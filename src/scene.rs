@@ -0,0 +1,622 @@
+use std::fs;
+use std::io;
+use std::path::{self, Component, Path, PathBuf};
+
+use glam::{Mat4, Quat, Vec3};
+use serde::{Deserialize, Serialize};
+
+use crate::animation::{CameraKeyframe, CameraPath, Interpolation, Orientation};
+use crate::camera;
+use crate::disc::{DiscModel, Redshift};
+use crate::error::Error;
+use crate::integrator::Integrator;
+use crate::metric::{Gradient, Metric};
+use crate::output::{self, Channel, ColorSpace, HdrFormat};
+use crate::render::{Device, Renderer};
+use crate::sky::{self, ProceduralSky, SkyFilter};
+use crate::tonemap::{self, ToneMapper, ToneMapping};
+
+/// Complete description of a render, stored as TOML so that renders can be reproduced from a file.
+///
+/// Every field is optional in the file and defaults to the value of [`Scene::default`]. Unknown fields are rejected
+/// to catch typos.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Scene {
+    pub camera: CameraSettings,
+    pub black_hole: BlackHoleSettings,
+    pub disc: DiscSettings,
+    pub sky: SkySettings,
+    pub sampling: SamplingSettings,
+    pub output: OutputSettings,
+    /// Renders a numbered frame sequence along a camera path instead of a single image.
+    pub animation: Option<AnimationSettings>,
+}
+
+/// Position and orientation of the camera, with the black hole at the origin and its spin axis along `z`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CameraSettings {
+    pub position: [f32; 3],
+    /// Point the camera looks at, unless `orientation` is given.
+    pub look_at: [f32; 3],
+    /// Direction towards the top of the image when using `look_at`.
+    pub up: [f32; 3],
+    /// Yaw, pitch and roll in degrees from looking along `+y`, see [`camera::from_euler`].
+    pub orientation: Option<[f32; 3]>,
+    /// Vertical field of view in degrees, which takes precedence over `focal_length`.
+    pub fov: Option<f32>,
+    /// Focal length in units of half the image height.
+    pub focal_length: f32,
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        Self {
+            position: [1.5139699, -16.080126, 2.293509],
+            look_at: [0.0; 3],
+            up: [0.0, 0.0, 1.0],
+            orientation: None,
+            fov: None,
+            focal_length: 1.5,
+        }
+    }
+}
+
+impl CameraSettings {
+    /// Keyframe at `time` of a [`CameraPath`] with this camera.
+    pub fn keyframe(&self, time: f32) -> Result<CameraKeyframe, Error> {
+        let (camera, focal_length) = self.view()?;
+        let orientation = match self.orientation {
+            Some(_) => Orientation::Rotation(Quat::from_mat4(&camera)),
+            None => Orientation::LookAt {
+                target: self.look_at.into(),
+                up: self.up.into(),
+            },
+        };
+
+        Ok(CameraKeyframe {
+            time,
+            position: self.position.into(),
+            orientation,
+            focal_length,
+        })
+    }
+
+    /// Camera matrix and focal length for [`Renderer::set_view`].
+    pub fn view(&self) -> Result<(Mat4, f32), Error> {
+        let camera = match self.orientation {
+            Some(angles) => {
+                let [yaw, pitch, roll] = angles.map(f32::to_radians);
+                camera::from_euler(yaw, pitch, roll)
+            }
+            None => camera::look_at(self.position.into(), self.look_at.into(), self.up.into())
+                .ok_or(Error::DegenerateCamera)?,
+        };
+
+        let focal_length = match self.fov {
+            Some(fov) if fov > 0.0 && fov < 180.0 => camera::focal_length(fov.to_radians()),
+            Some(fov) => {
+                return Err(Error::InvalidParameter {
+                    name: "field of view",
+                    value: fov,
+                })
+            }
+            None if self.focal_length.is_finite() && self.focal_length > 0.0 => self.focal_length,
+            None => {
+                return Err(Error::InvalidParameter {
+                    name: "focal length",
+                    value: self.focal_length,
+                })
+            }
+        };
+
+        Ok((camera, focal_length))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BlackHoleSettings {
+    pub metric: Metric,
+    pub mass: f32,
+    /// Spin parameter `a = J/M`.
+    pub spin: f32,
+    pub charge: f32,
+    pub allow_naked_singularities: bool,
+    /// Radius of the sphere in which light is lensed.
+    pub lensing_radius: f32,
+}
+
+impl Default for BlackHoleSettings {
+    fn default() -> Self {
+        Self {
+            metric: Metric::KerrNewman,
+            mass: 1.0,
+            spin: 0.3,
+            charge: 0.2,
+            allow_naked_singularities: false,
+            lensing_radius: 120.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiscSettings {
+    pub enabled: bool,
+    pub model: DiscModel,
+    /// Peak temperature of the thin disc in Kelvin.
+    pub temperature: f32,
+    pub redshift: Redshift,
+}
+
+impl Default for DiscSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            model: DiscModel::Volumetric,
+            temperature: 8000.0,
+            redshift: Redshift::Bolometric,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SkySettings {
+    pub enabled: bool,
+    /// Directory with the faces of a skybox or an equirectangular panorama, see [`sky::load`]. The embedded skybox
+    /// is used if unset.
+    pub path: Option<PathBuf>,
+    /// Rotation of the sky in degrees about the `x`, `y` and `z` axes, in that order, see [`sky::rotation`].
+    pub rotation: [f32; 3],
+    /// Factor by which the radiance of the sky is multiplied.
+    pub intensity: f32,
+    pub filter: SkyFilter,
+    /// Procedural sky rendered instead of the skybox, if set.
+    pub procedural: Option<ProceduralSky>,
+}
+
+impl Default for SkySettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: None,
+            rotation: [0.0; 3],
+            intensity: 1.0,
+            filter: SkyFilter::Bilinear,
+            procedural: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SamplingSettings {
+    /// Number of samples per pixel, each traced in its own frame.
+    pub samples: u32,
+    pub seed: u32,
+    pub integrator: Integrator,
+    pub tolerance: f32,
+    pub gradient: Gradient,
+}
+
+impl Default for SamplingSettings {
+    fn default() -> Self {
+        Self {
+            samples: 16,
+            seed: 0,
+            integrator: Integrator::Euler,
+            tolerance: 1e-4,
+            gradient: Gradient::Analytic,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputSettings {
+    /// Path of the image. OpenEXR (`.exr`) and Radiance HDR (`.hdr`) store the linear radiance without tone mapping.
+    pub path: PathBuf,
+    pub width: u32,
+    pub height: u32,
+    pub tone_mapping: ToneMapping,
+    /// Exposure in stops, added to the automatic exposure if that is enabled.
+    pub exposure: f32,
+    /// Expose the luminance at this percentile of the lit pixels to one.
+    pub auto_exposure: Option<f32>,
+    /// Luminance mapped to white by the extended Reinhard operator.
+    pub white_point: f32,
+    /// Primaries and transfer function of tone-mapped images, which need a format that can embed an ICC profile
+    /// unless it is sRGB.
+    pub color_space: ColorSpace,
+    /// Bits per channel of tone-mapped images, 8 or 16. 16 bits are supported by PNG and TIFF.
+    pub bit_depth: u8,
+    /// Extra channels written next to the radiance, only supported by OpenEXR.
+    pub channels: Vec<Channel>,
+    /// Also write the integration diagnostics next to the image.
+    pub diagnostics: bool,
+    /// Draw the predicted shadow over the image and compare it with the rendered one. The shadow is only drawn on
+    /// tone-mapped images.
+    pub shadow_overlay: bool,
+}
+
+impl Default for OutputSettings {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from("black-hole.png"),
+            width: 1920,
+            height: 1080,
+            tone_mapping: ToneMapping::Exponential,
+            exposure: 0.0,
+            auto_exposure: None,
+            white_point: ToneMapper::WHITE,
+            color_space: ColorSpace::Srgb,
+            bit_depth: 8,
+            channels: Vec::new(),
+            diagnostics: false,
+            shadow_overlay: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AnimationSettings {
+    /// Frames per second, from the first keyframe up to the last one.
+    pub fps: f32,
+    pub interpolation: Interpolation,
+    pub keyframes: Vec<Keyframe>,
+}
+
+impl Default for AnimationSettings {
+    fn default() -> Self {
+        Self {
+            fps: 24.0,
+            interpolation: Interpolation::CatmullRom,
+            keyframes: Vec::new(),
+        }
+    }
+}
+
+impl AnimationSettings {
+    /// Camera path through the keyframes, which take the settings they leave out from `camera`.
+    pub fn path(&self, camera: &CameraSettings) -> Result<CameraPath, Error> {
+        if !(self.fps.is_finite() && self.fps > 0.0) {
+            return Err(Error::InvalidParameter {
+                name: "frames per second",
+                value: self.fps,
+            });
+        }
+
+        let keyframes = self
+            .keyframes
+            .iter()
+            .map(|keyframe| keyframe.camera(camera).keyframe(keyframe.time))
+            .collect::<Result<_, _>>()?;
+
+        CameraPath::new(keyframes, self.interpolation)
+    }
+}
+
+/// Camera at a point in time of an animation, with the settings of [`CameraSettings`].
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Keyframe {
+    /// Time in seconds.
+    pub time: f32,
+    pub position: Option<[f32; 3]>,
+    pub look_at: Option<[f32; 3]>,
+    pub up: Option<[f32; 3]>,
+    pub orientation: Option<[f32; 3]>,
+    pub fov: Option<f32>,
+    pub focal_length: Option<f32>,
+}
+
+impl Keyframe {
+    /// Camera of this keyframe, taking the settings it leaves out from `base`.
+    pub fn camera(&self, base: &CameraSettings) -> CameraSettings {
+        let mut camera = base.clone();
+        camera.position = self.position.unwrap_or(camera.position);
+        camera.look_at = self.look_at.unwrap_or(camera.look_at);
+        camera.up = self.up.unwrap_or(camera.up);
+        if self.look_at.is_some() || self.up.is_some() {
+            camera.orientation = None;
+        }
+        if self.orientation.is_some() {
+            camera.orientation = self.orientation;
+        }
+        if self.fov.is_some() || self.focal_length.is_some() {
+            camera.fov = self.fov;
+        }
+        camera.focal_length = self.focal_length.unwrap_or(camera.focal_length);
+
+        camera
+    }
+}
+
+impl OutputSettings {
+    /// Rejects bit depths and extra channels that the format cannot hold, before anything is rendered.
+    pub fn validate(&self) -> Result<(), Error> {
+        let hdr = HdrFormat::from_path(&self.path).is_some();
+        match self.bit_depth {
+            8 => {}
+            16 if hdr || output::supports_16_bit(&self.path) => {}
+            16 => return Err(Error::UnsupportedBitDepth(self.path.clone())),
+            _ => {
+                return Err(Error::InvalidParameter {
+                    name: "bit depth",
+                    value: self.bit_depth as f32,
+                })
+            }
+        }
+
+        if !self.channels.is_empty() && HdrFormat::from_path(&self.path) != Some(HdrFormat::OpenExr) {
+            return Err(Error::UnsupportedChannels(self.path.clone()));
+        }
+
+        if self.color_space != ColorSpace::Srgb && !hdr && !output::supports_color_profile(&self.path) {
+            return Err(Error::UnsupportedColorSpace(self.path.clone()));
+        }
+
+        Ok(())
+    }
+
+    /// Tone mapping of the accumulated render `target`, which is only needed for the automatic exposure.
+    pub fn tone_mapper(&self, target: &[u8]) -> Result<ToneMapper, Error> {
+        let auto_exposure = match self.auto_exposure {
+            Some(percentile) => tonemap::auto_exposure(&output::linear_pixels(target), percentile)?,
+            None => 0.0,
+        };
+
+        ToneMapper::new(self.tone_mapping)
+            .with_exposure(auto_exposure + self.exposure)?
+            .with_white(self.white_point)
+    }
+}
+
+impl Scene {
+    /// Parses a scene from TOML. Relative sky paths are resolved against `base`, usually the directory of the file.
+    pub fn from_toml(s: &str, base: &Path) -> Result<Self, toml::de::Error> {
+        let mut scene: Scene = toml::from_str(s)?;
+
+        if let Some(path) = &mut scene.sky.path {
+            *path = base.join(&*path);
+        }
+
+        Ok(scene)
+    }
+
+    pub fn to_toml(&self) -> String {
+        let mut value = toml::Value::try_from(self).expect("scenes only contain types that TOML can represent");
+        shorten_floats(&mut value);
+        toml::to_string(&value).expect("scenes only contain types that TOML can represent")
+    }
+
+    /// Reads and parses a scene file, see [`Scene::from_toml`].
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let toml = fs::read_to_string(path).map_err(|error| Error::Io {
+            path: path.to_path_buf(),
+            error,
+        })?;
+        let base = path.parent().unwrap_or(Path::new(""));
+
+        Self::from_toml(&toml, base).map_err(|error| Error::Scene {
+            path: path.to_path_buf(),
+            error,
+        })
+    }
+
+    /// Writes the scene to a file, which can be loaded again with [`Scene::load`]. The sky path is rewritten relative
+    /// to the directory of the file.
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let io_error = |error| Error::Io {
+            path: path.to_path_buf(),
+            error,
+        };
+
+        let mut scene = self.clone();
+        if let Some(sky) = &mut scene.sky.path {
+            *sky = relative_path(sky, path.parent().unwrap_or(Path::new(""))).map_err(io_error)?;
+        }
+
+        fs::write(path, scene.to_toml()).map_err(io_error)
+    }
+
+    /// Creates a renderer on `device` with the resolution of the output and configures it with [`Scene::configure`].
+    pub fn build(&self, device: Device) -> Result<Renderer, Error> {
+        self.output.validate()?;
+        let mut renderer = Renderer::with_device(self.output.width, self.output.height, device)?;
+        self.configure(&mut renderer)?;
+
+        Ok(renderer)
+    }
+
+    /// Applies every setting except the resolution and output to `renderer`, stopping at the first invalid one.
+    pub fn configure(&self, renderer: &mut Renderer) -> Result<(), Error> {
+        if self.sampling.samples == 0 {
+            return Err(Error::InvalidParameter {
+                name: "samples",
+                value: 0.0,
+            });
+        }
+
+        let (camera, focal_length) = self.camera.view()?;
+        renderer.set_view(camera, self.camera.position.into(), focal_length);
+
+        let black_hole = &self.black_hole;
+        renderer.set_lensing_radius(black_hole.lensing_radius)?;
+        renderer.set_spacetime(
+            black_hole.metric,
+            black_hole.mass,
+            black_hole.spin,
+            black_hole.charge,
+            black_hole.allow_naked_singularities,
+        )?;
+
+        renderer.set_render_disc(self.disc.enabled);
+        renderer.set_disc_model(self.disc.model);
+        renderer.set_disc_temperature(self.disc.temperature)?;
+        renderer.set_redshift(self.disc.redshift);
+
+        if let Some(path) = &self.sky.path {
+            renderer.set_skybox_from_path(path)?;
+        }
+        renderer.set_sky_intensity(self.sky.intensity)?;
+        renderer.set_sky_filter(self.sky.filter);
+        renderer.set_procedural_sky(self.sky.procedural)?;
+        renderer.set_sky_rotation(sky::rotation(Vec3::from(self.sky.rotation.map(f32::to_radians))));
+        renderer.set_render_skybox(self.sky.enabled);
+
+        renderer.set_frames(self.sampling.samples);
+        renderer.set_seed(self.sampling.seed);
+        renderer.set_integrator(self.sampling.integrator);
+        renderer.set_tolerance(self.sampling.tolerance)?;
+        renderer.set_gradient(self.sampling.gradient);
+
+        let channels_need_diagnostics = self.output.channels.iter().any(|channel| channel.needs_diagnostics());
+        renderer.set_diagnostics(self.output.diagnostics || channels_need_diagnostics);
+        renderer.set_shadow_overlay(self.output.shadow_overlay);
+
+        Ok(())
+    }
+}
+
+/// Path that leads from the directory `base` to `path`, or the absolute `path` if there is none.
+fn relative_path(path: &Path, base: &Path) -> io::Result<PathBuf> {
+    // Resolve symbolic links and `..` where the paths exist
+    let resolve = |path: &Path| fs::canonicalize(path).or_else(|_| path::absolute(path));
+    let (path, base) = (resolve(path)?, resolve(base)?);
+
+    let common = path
+        .components()
+        .zip(base.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+    if common == 0 {
+        return Ok(path);
+    }
+
+    let mut relative: PathBuf = base.components().skip(common).map(|_| Component::ParentDir).collect();
+    relative.extend(path.components().skip(common));
+    if relative.as_os_str().is_empty() {
+        relative.push(Component::CurDir);
+    }
+    Ok(relative)
+}
+
+/// Rounds floats to the shortest representation of the `f32` they were converted from, so that e.g. `0.2f32` is
+/// written as `0.2` rather than `0.20000000298023224`.
+fn shorten_floats(value: &mut toml::Value) {
+    match value {
+        toml::Value::Float(f) => *f = (*f as f32).to_string().parse().unwrap_or(*f),
+        toml::Value::Array(array) => array.iter_mut().for_each(shorten_floats),
+        toml::Value::Table(table) => table.iter_mut().for_each(|(_, v)| shorten_floats(v)),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use glam::Vec3;
+
+    use super::Scene;
+    use crate::camera;
+    use crate::integrator::Integrator;
+    use crate::metric::Metric;
+    use crate::render::Renderer;
+
+    #[test]
+    fn scene_round_trips_through_toml() {
+        let mut scene = Scene::default();
+        scene.black_hole.metric = Metric::ReissnerNordstrom;
+        scene.sampling.integrator = Integrator::DormandPrince;
+        scene.camera.orientation = Some([10.0, -5.0, 0.0]);
+
+        let parsed = Scene::from_toml(&scene.to_toml(), Path::new("")).unwrap();
+        assert_eq!(parsed, scene);
+    }
+
+    #[test]
+    fn missing_fields_take_defaults() {
+        let scene = Scene::from_toml(
+            "[black_hole]\nspin = 0.9\n\n[sky]\npath = \"sky2\"\n",
+            Path::new("images"),
+        )
+        .unwrap();
+
+        assert_eq!(scene.black_hole.spin, 0.9);
+        assert_eq!(scene.black_hole.mass, 1.0);
+        assert_eq!(scene.sky.path.as_deref(), Some(Path::new("images/sky2")));
+        assert_eq!(scene.output, Scene::default().output);
+
+        assert!(Scene::from_toml("[black_hole]\nspni = 0.9\n", Path::new("")).is_err());
+    }
+
+    #[test]
+    fn configure_replaces_the_previous_black_hole() {
+        let mut renderer = Renderer::new_cpu(2, 2).unwrap();
+
+        let mut naked = Scene::default();
+        naked.black_hole.metric = Metric::KerrNewman;
+        naked.black_hole.spin = 1.2;
+        naked.black_hole.allow_naked_singularities = true;
+        naked.configure(&mut renderer).unwrap();
+        Scene::default().configure(&mut renderer).unwrap();
+
+        // Spin and charge that are only valid while the metric ignores them
+        let mut schwarzschild = Scene::default();
+        schwarzschild.black_hole.metric = Metric::Schwarzschild;
+        schwarzschild.black_hole.spin = 0.99;
+        schwarzschild.black_hole.charge = 0.5;
+        schwarzschild.configure(&mut renderer).unwrap();
+        let mut kerr_newman = Scene::default();
+        kerr_newman.black_hole.metric = Metric::KerrNewman;
+        kerr_newman.black_hole.spin = 0.8;
+        kerr_newman.black_hole.charge = 0.3;
+        kerr_newman.configure(&mut renderer).unwrap();
+
+        naked.black_hole.allow_naked_singularities = false;
+        assert!(naked.configure(&mut renderer).is_err());
+    }
+
+    #[test]
+    fn example_scene_parses() {
+        let scene = Scene::from_toml(include_str!("../scenes/thin-disc.toml"), Path::new("scenes")).unwrap();
+        assert_eq!(scene.black_hole.spin, 0.9);
+        assert!(scene.sky.path.unwrap().join("right.png").exists());
+    }
+
+    #[test]
+    fn saved_sky_paths_are_relative_to_the_file() {
+        let scene = Scene::load(Path::new("scenes/thin-disc.toml")).unwrap();
+        let dir = std::env::temp_dir().join(format!("black-hole-scene-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("nested")).unwrap();
+
+        let path = dir.join("nested/dump.toml");
+        scene.save(&path).unwrap();
+        let written = Scene::from_toml(&std::fs::read_to_string(&path).unwrap(), Path::new("")).unwrap();
+        let sky = Scene::load(&path).unwrap().sky.path.unwrap();
+        let exists = sky.join("right.png").exists();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(written.sky.path.unwrap().starts_with(".."));
+        assert!(exists, "{sky:?}");
+    }
+
+    #[test]
+    fn example_animation_builds_a_path() {
+        let scene = Scene::from_toml(include_str!("../scenes/orbit.toml"), Path::new("scenes")).unwrap();
+        let animation = scene.animation.unwrap();
+        let path = animation.path(&scene.camera).unwrap();
+        assert_eq!(path.frame_times(animation.fps).count(), 49);
+
+        // The last keyframe narrows the field of view given by the scene camera
+        let (_, position, focal_length) = path.view(2.0);
+        assert_eq!(position, Vec3::new(-25.0, 0.0, 10.0));
+        assert!((focal_length - camera::focal_length(30f32.to_radians())).abs() < 1e-5);
+    }
+}
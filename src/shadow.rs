@@ -0,0 +1,311 @@
+use std::fmt;
+
+use glam::{Mat3, Mat4, Vec2, Vec3};
+use image::{ImageBuffer, Pixel};
+
+use crate::black_hole::BlackHole;
+use crate::diagnostics::{Diagnostics, Termination};
+use crate::geometry;
+use crate::types::View;
+
+/// Number of points on the predicted shadow boundary.
+const SHADOW_SAMPLES: usize = 512;
+
+/// Maps between pixels of the rendered image and celestial coordinates `(α, β)` of the camera.
+///
+/// The celestial coordinates are those of [`geometry::shadow`], with `β` along the spin axis projected onto the sky
+/// and `α` perpendicular to it. A photon arriving with impact parameter `b` is seen at an angle `ψ` from the black
+/// hole with `sin ψ = b sqrt(1 - 2M/r + Q²/r²) / r`, which is exact for a static observer at radius `r` in the
+/// Reissner-Nordström metric and neglects the frame dragging of the spin otherwise.
+pub struct Projection {
+    camera: Mat3,
+    inverse_camera: Mat3,
+    focal_length: f32,
+    resolution: Vec2,
+    forward: Vec3,
+    alpha: Vec3,
+    beta: Vec3,
+    /// `r / sqrt(1 - 2M/r + Q²/r²)`, the impact parameter of a photon arriving perpendicular to the line of sight.
+    scale: f32,
+    inclination: f32,
+}
+
+impl Projection {
+    /// Returns `None` if the camera is at the centre or not outside the horizon.
+    pub(crate) fn new(black_hole: &BlackHole, view: &View) -> Option<Self> {
+        let position = Vec3::from_array(view.position);
+        let r = position.length();
+        let lapse = 1.0 - 2.0 * black_hole.mass / r + black_hole.charge * black_hole.charge / (r * r);
+        if r == 0.0 || lapse <= 0.0 {
+            return None;
+        }
+
+        let camera = Mat3::from_mat4(Mat4::from_cols_array(&view.camera));
+        let forward = -position / r;
+
+        // Seen along the spin axis the orientation of the sky is arbitrary
+        let axis = Vec3::Z - Vec3::Z.dot(forward) * forward;
+        let beta = axis.try_normalize().unwrap_or_else(|| forward.any_orthonormal_vector());
+        let alpha = forward.cross(beta);
+
+        Some(Self {
+            camera,
+            inverse_camera: camera.inverse(),
+            focal_length: view.focal_length,
+            resolution: Vec2::new(view.resolution[0] as f32, view.resolution[1] as f32),
+            forward,
+            alpha,
+            beta,
+            scale: r / lapse.sqrt(),
+            inclination: (position.z / r).clamp(-1.0, 1.0).acos(),
+        })
+    }
+
+    /// Angle between the spin axis and the direction from the black hole to the camera.
+    pub fn inclination(&self) -> f32 {
+        self.inclination
+    }
+
+    /// Image coordinates of a celestial position, with pixel `(x, y)` covering `[x, x + 1) × [y, y + 1)` and the
+    /// top row first. Returns `None` for positions behind the camera or outside the sky.
+    pub fn to_pixel(&self, celestial: Vec2) -> Option<Vec2> {
+        let v = celestial / self.scale;
+        let cos = 1.0 - v.length_squared();
+        if cos <= 0.0 {
+            return None;
+        }
+
+        let direction = self.inverse_camera * (cos.sqrt() * self.forward + v.x * self.alpha + v.y * self.beta);
+        if direction.z <= 0.0 {
+            return None;
+        }
+
+        // Inverse of the ray generation in `pathtrace.wgsl`, whose fragment coordinates match the image coordinates
+        let pos = direction.truncate() * self.focal_length / direction.z;
+        Some(0.5 * (pos * self.resolution.y + self.resolution))
+    }
+
+    /// Celestial position seen at image coordinates `pixel`, or `None` if it faces away from the black hole.
+    pub fn to_celestial(&self, pixel: Vec2) -> Option<Vec2> {
+        let pos = (2.0 * pixel - self.resolution) / self.resolution.y;
+        let direction = (self.camera * pos.extend(self.focal_length)).normalize();
+        if direction.dot(self.forward) <= 0.0 {
+            return None;
+        }
+
+        Some(self.scale * Vec2::new(direction.dot(self.alpha), direction.dot(self.beta)))
+    }
+}
+
+/// Size, position and shape of a shadow in celestial coordinates.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ShadowMetrics {
+    /// Diameter of the circle with the same area.
+    pub diameter: f32,
+    /// Extent along `α`, perpendicular to the projected spin axis.
+    pub width: f32,
+    /// Extent along `β`, the projected spin axis.
+    pub height: f32,
+    /// Centre of area, which is offset from the black hole along `α` by the spin.
+    pub centroid: Vec2,
+    /// `1 - width / height`, which is zero for a circle and grows as the prograde side flattens.
+    pub asymmetry: f32,
+}
+
+impl ShadowMetrics {
+    /// Metrics of the region enclosed by a closed polygon.
+    pub fn from_curve(curve: &[Vec2]) -> Self {
+        let mut area = 0.0;
+        let mut centroid = Vec2::ZERO;
+
+        for (i, &p) in curve.iter().enumerate() {
+            let q = curve[(i + 1) % curve.len()];
+            let cross = p.perp_dot(q);
+            area += 0.5 * cross;
+            centroid += (p + q) * cross / 6.0;
+        }
+
+        let (min, max) = bounds(curve.iter().copied());
+        Self::new(area.abs(), max - min, centroid / area)
+    }
+
+    /// Metrics of the pixels whose rays were captured by the black hole, or `None` if there are none.
+    pub fn from_diagnostics(diagnostics: &Diagnostics, projection: &Projection) -> Option<Self> {
+        let (width, height) = diagnostics.size();
+        let mut area = 0.0;
+        let mut centroid = Vec2::ZERO;
+        let mut points = Vec::new();
+
+        for y in 0..height {
+            for x in 0..width {
+                if diagnostics.termination(x, y) != Some(Termination::Captured) {
+                    continue;
+                }
+
+                let pixel = Vec2::new(x as f32, y as f32);
+                let corners = [Vec2::ZERO, Vec2::X, Vec2::Y].map(|d| projection.to_celestial(pixel + d));
+                let [Some(c0), Some(c1), Some(c2)] = corners else {
+                    continue;
+                };
+                let Some(center) = projection.to_celestial(pixel + 0.5) else {
+                    continue;
+                };
+
+                let pixel_area = (c1 - c0).perp_dot(c2 - c0).abs();
+                area += pixel_area;
+                centroid += center * pixel_area;
+                points.push(center);
+            }
+        }
+
+        if points.is_empty() {
+            return None;
+        }
+
+        // Pixel centres lie half a pixel inside the boundary on either side
+        let pixel_size = (area / points.len() as f32).sqrt();
+        let (min, max) = bounds(points.into_iter());
+
+        Some(Self::new(area, max - min + pixel_size, centroid / area))
+    }
+
+    fn new(area: f32, extent: Vec2, centroid: Vec2) -> Self {
+        Self {
+            diameter: 2.0 * (area / std::f32::consts::PI).sqrt(),
+            width: extent.x,
+            height: extent.y,
+            centroid,
+            asymmetry: 1.0 - extent.x / extent.y,
+        }
+    }
+}
+
+fn bounds(points: impl Iterator<Item = Vec2>) -> (Vec2, Vec2) {
+    points.fold((Vec2::INFINITY, Vec2::NEG_INFINITY), |(min, max), p| {
+        (min.min(p), max.max(p))
+    })
+}
+
+/// Comparison of the rendered shadow with the analytic prediction.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ShadowReport {
+    pub inclination: f32,
+    pub predicted: ShadowMetrics,
+    pub rendered: ShadowMetrics,
+}
+
+impl fmt::Display for ShadowReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (p, r) = (&self.predicted, &self.rendered);
+        let rows = [
+            ("diameter", p.diameter, r.diameter),
+            ("width", p.width, r.width),
+            ("height", p.height, r.height),
+            ("centroid offset (α)", p.centroid.x, r.centroid.x),
+            ("centroid offset (β)", p.centroid.y, r.centroid.y),
+            ("asymmetry", p.asymmetry, r.asymmetry),
+        ];
+
+        writeln!(f, "{:<26}{:.2}°", "inclination", self.inclination.to_degrees())?;
+        writeln!(f, "{:<26}{:>12}{:>12}{:>12}", "", "predicted", "rendered", "difference")?;
+        for (name, predicted, rendered) in rows {
+            writeln!(
+                f,
+                "{name:<26}{predicted:>12.6}{rendered:>12.6}{:>12.6}",
+                rendered - predicted
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Analytically predicted shadow of a black hole as seen by the camera, compared with a rendered image.
+pub struct Shadow {
+    /// Predicted boundary in image coordinates, with points that cannot be projected left out.
+    pub curve: Vec<Vec2>,
+    /// `None` if no pixel was captured by the black hole.
+    pub report: Option<ShadowReport>,
+}
+
+impl Shadow {
+    /// Predicts the shadow for the camera of `view` and compares it with the captured pixels of `diagnostics`.
+    ///
+    /// Returns `None` if the black hole has no shadow, e.g. because it is a naked singularity, or the camera is not
+    /// outside its horizon. Pixels covered by the accretion disc are not captured, so the disc should be disabled
+    /// for a meaningful report.
+    pub(crate) fn new(black_hole: &BlackHole, view: &View, diagnostics: &Diagnostics) -> Option<Self> {
+        let projection = Projection::new(black_hole, view)?;
+
+        let celestial = geometry::shadow(black_hole, projection.inclination(), SHADOW_SAMPLES);
+        if celestial.is_empty() {
+            return None;
+        }
+
+        let curve = celestial.iter().filter_map(|&p| projection.to_pixel(p)).collect();
+        let report = ShadowMetrics::from_diagnostics(diagnostics, &projection).map(|rendered| ShadowReport {
+            inclination: projection.inclination(),
+            predicted: ShadowMetrics::from_curve(&celestial),
+            rendered,
+        });
+
+        Some(Self { curve, report })
+    }
+
+    /// Draws the predicted boundary onto `image`, which must have the resolution of the view.
+    pub fn draw<P: Pixel>(&self, image: &mut ImageBuffer<P, Vec<P::Subpixel>>, color: P) {
+        for (i, &p) in self.curve.iter().enumerate() {
+            let q = self.curve[(i + 1) % self.curve.len()];
+            let steps = (q - p).abs().max_element().ceil().max(1.0) as u32;
+
+            for s in 0..=steps {
+                let point = p.lerp(q, s as f32 / steps as f32);
+                let (x, y) = (point.x.floor(), point.y.floor());
+
+                if x >= 0.0 && y >= 0.0 && (x as u32) < image.width() && (y as u32) < image.height() {
+                    image.put_pixel(x as u32, y as u32, color);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytemuck::Zeroable;
+    use glam::{Mat4, Vec2};
+
+    use super::{Projection, ShadowMetrics};
+    use crate::black_hole::BlackHole;
+    use crate::types::View;
+
+    #[test]
+    fn projection_round_trips() {
+        let view = View {
+            camera: Mat4::IDENTITY.to_cols_array(),
+            position: [3.0, 1.0, -40.0],
+            focal_length: 1.5,
+            resolution: [320, 180],
+            ..View::zeroed()
+        };
+        let projection = Projection::new(&BlackHole::new(1.0, 0.5, 0.2), &view).unwrap();
+
+        for celestial in [Vec2::ZERO, Vec2::new(5.0, -2.0), Vec2::new(-3.0, 4.5)] {
+            let pixel = projection.to_pixel(celestial).unwrap();
+            let back = projection.to_celestial(pixel).unwrap();
+            assert!((back - celestial).length() < 1e-3, "{celestial} -> {pixel} -> {back}");
+        }
+    }
+
+    #[test]
+    fn circle_metrics() {
+        let curve: Vec<_> = (0..256)
+            .map(|i| 3.0 * Vec2::from_angle(std::f32::consts::TAU * i as f32 / 256.0) + Vec2::X)
+            .collect();
+        let metrics = ShadowMetrics::from_curve(&curve);
+
+        assert!((metrics.diameter - 6.0).abs() < 1e-2);
+        assert!((metrics.centroid - Vec2::X).length() < 1e-4);
+        assert!(metrics.asymmetry.abs() < 1e-3);
+    }
+}
@@ -0,0 +1,352 @@
+//! Skyboxes are linear `Rgba32F` images. 8-bit images are converted to `[0, 1]` without decoding their transfer
+//! function, and HDR images such as Radiance `.hdr` or OpenEXR keep their full range.
+
+use std::f32::consts::{PI, TAU};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use glam::{Mat3, Vec3, Vec4};
+use image::{ImageFormat, Rgba, Rgba32FImage};
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+
+/// How the sky texture is filtered.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SkyFilter {
+    /// Bilinear filtering of the full resolution texture, which aliases where lensing demagnifies the sky.
+    #[default]
+    Bilinear,
+    /// Trilinear filtering with the mip level selected from the divergence of the rays of neighbouring pixels,
+    /// which needs fewer samples for a clean sky near the shadow.
+    Footprint,
+}
+
+impl FromStr for SkyFilter {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "bilinear" => Ok(SkyFilter::Bilinear),
+            "footprint" => Ok(SkyFilter::Footprint),
+            _ => Err(Error::UnknownOption {
+                name: "sky filter",
+                value: s.to_string(),
+            }),
+        }
+    }
+}
+
+/// Procedural sky of point stars and a galactic band, which needs no textures.
+///
+/// The band lies along the equator of the sky, the `xy` plane before [`rotation`], with the bulge of the galaxy
+/// towards `+x`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProceduralSky {
+    /// Seed of the positions, fluxes and colours of the stars.
+    pub seed: u32,
+    /// Average number of stars per square degree away from the galactic band, where they are up to three times
+    /// denser.
+    pub density: f32,
+    /// Flux of the faintest stars, as the area in square arcminutes of a patch of unit radiance that is as bright.
+    pub brightness: f32,
+    /// Radiance of the galactic band, or zero for none.
+    pub galaxy: f32,
+}
+
+impl Default for ProceduralSky {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            density: 2.0,
+            brightness: 10.0,
+            galaxy: 1.0,
+        }
+    }
+}
+
+/// File names of the cubemap faces in layer order, without extension.
+pub const FACE_NAMES: [&str; 6] = ["right", "left", "top", "bottom", "front", "back"];
+
+/// Loads the faces of the embedded skybox in cubemap layer order: right, left, top, bottom, front, back.
+pub fn default_faces() -> Result<[Rgba32FImage; 6], Error> {
+    let embedded = [
+        include_bytes!("../images/sky1/right.png").as_slice(),
+        include_bytes!("../images/sky1/left.png"),
+        include_bytes!("../images/sky1/top.png"),
+        include_bytes!("../images/sky1/bottom.png"),
+        include_bytes!("../images/sky1/front.png"),
+        include_bytes!("../images/sky1/back.png"),
+    ];
+
+    let mut faces: [Rgba32FImage; 6] = Default::default();
+
+    for ((face, name), bytes) in faces.iter_mut().zip(FACE_NAMES).zip(embedded) {
+        *face = image::load_from_memory(bytes)
+            .map_err(|error| Error::Image {
+                path: PathBuf::from(format!("images/sky1/{name}.png")),
+                error,
+            })?
+            .to_rgba32f();
+    }
+
+    Ok(faces)
+}
+
+/// Loads the faces of a skybox from the images named `right`, `left`, `top`, `bottom`, `front` and `back` in `dir`,
+/// in cubemap layer order. Each face may be in any format supported by [`image`], e.g. `right.png` or `right.jpg`.
+///
+/// Fails with [`Error::MissingSkyboxFaces`] listing every face that was not found, and with
+/// [`Error::InvalidSkyboxFace`] if the faces are not square and of equal size.
+pub fn load_faces(dir: &Path) -> Result<[Rgba32FImage; 6], Error> {
+    let io_error = |error| Error::Io {
+        path: dir.to_path_buf(),
+        error,
+    };
+
+    let mut entries = fs::read_dir(dir)
+        .map_err(io_error)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(io_error)?;
+    entries.sort();
+
+    let paths = FACE_NAMES.map(|name| {
+        entries
+            .iter()
+            .find(|path| path.file_stem().is_some_and(|stem| stem == name) && ImageFormat::from_path(path).is_ok())
+            .cloned()
+    });
+
+    let missing: Vec<_> = FACE_NAMES
+        .iter()
+        .zip(&paths)
+        .filter(|(_, path)| path.is_none())
+        .map(|(&name, _)| name)
+        .collect();
+    if !missing.is_empty() {
+        return Err(Error::MissingSkyboxFaces {
+            dir: dir.to_path_buf(),
+            faces: missing,
+        });
+    }
+
+    let mut faces: [Rgba32FImage; 6] = Default::default();
+
+    for (face, path) in faces.iter_mut().zip(paths.into_iter().flatten()) {
+        *face = image::open(&path)
+            .map_err(|error| Error::Image { path, error })?
+            .to_rgba32f();
+    }
+
+    validate_faces(&faces)?;
+
+    Ok(faces)
+}
+
+/// Checks that cubemap faces in layer order are square and of the size of the first one.
+pub fn validate_faces(faces: &[Rgba32FImage; 6]) -> Result<(), Error> {
+    let size = faces[0].width();
+
+    for (face, name) in faces.iter().zip(FACE_NAMES) {
+        let (width, height) = face.dimensions();
+        if width != size || height != size || size == 0 {
+            return Err(Error::InvalidSkyboxFace {
+                face: name,
+                width,
+                height,
+                size,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Loads a skybox from either a directory of faces, see [`load_faces`], or an equirectangular panorama, see
+/// [`faces_from_equirectangular`].
+pub fn load(path: &Path) -> Result<[Rgba32FImage; 6], Error> {
+    if path.is_dir() {
+        return load_faces(path);
+    }
+
+    let image = image::open(path).map_err(|error| Error::Image {
+        path: path.to_path_buf(),
+        error,
+    })?;
+
+    faces_from_equirectangular(&image.to_rgba32f())
+}
+
+/// Resamples an equirectangular panorama into cubemap faces in layer order, each a quarter of its width.
+///
+/// The poles of the panorama lie on the `z` axis, the spin axis of the black hole, with the top row towards `+z`, so
+/// that its horizon is the equatorial plane. The centre of the panorama faces `+x` and it is seen unmirrored from the
+/// inside.
+///
+/// Fails with [`Error::EmptyPanorama`] if the panorama has no pixels.
+pub fn faces_from_equirectangular(image: &Rgba32FImage) -> Result<[Rgba32FImage; 6], Error> {
+    let (width, height) = image.dimensions();
+    if width == 0 || height == 0 {
+        return Err(Error::EmptyPanorama { width, height });
+    }
+    let size = (width / 4).max(1);
+
+    let texel = |x: i64, y: i64| {
+        let x = x.rem_euclid(width as i64) as u32;
+        let y = y.clamp(0, height as i64 - 1) as u32;
+        Vec4::from_array(image.get_pixel(x, y).0)
+    };
+
+    Ok(std::array::from_fn(|face| {
+        Rgba32FImage::from_fn(size, size, |x, y| {
+            let sc = 2.0 * (x as f32 + 0.5) / size as f32 - 1.0;
+            let tc = 2.0 * (y as f32 + 0.5) / size as f32 - 1.0;
+            let dir = face_direction(face, sc, tc).normalize();
+
+            // Longitude decreases to the right, as seen from the inside with `+z` up
+            let u = 0.5 - dir.y.atan2(dir.x) / TAU;
+            let v = dir.z.clamp(-1.0, 1.0).acos() / PI;
+
+            // Bilinear interpolation, wrapping around in longitude
+            let px = u * width as f32 - 0.5;
+            let py = v * height as f32 - 0.5;
+            let (x0, y0) = (px.floor(), py.floor());
+            let (fx, fy) = (px - x0, py - y0);
+            let (x0, y0) = (x0 as i64, y0 as i64);
+
+            let color = texel(x0, y0)
+                .lerp(texel(x0 + 1, y0), fx)
+                .lerp(texel(x0, y0 + 1).lerp(texel(x0 + 1, y0 + 1), fx), fy);
+
+            Rgba(color.to_array())
+        })
+    }))
+}
+
+/// Mip chain of a cubemap face, starting with the face itself and halving its size with a box filter down to a
+/// single texel.
+pub fn mip_chain(face: &Rgba32FImage) -> Vec<Rgba32FImage> {
+    let mut levels = vec![face.clone()];
+
+    while let Some(last) = levels.last().filter(|level| level.width() > 1 || level.height() > 1) {
+        let (width, height) = last.dimensions();
+        let texel = |x: u32, y: u32| Vec4::from_array(last.get_pixel(x.min(width - 1), y.min(height - 1)).0);
+
+        let next = Rgba32FImage::from_fn((width / 2).max(1), (height / 2).max(1), |x, y| {
+            let (x, y) = (2 * x, 2 * y);
+            let sum = texel(x, y) + texel(x + 1, y) + texel(x, y + 1) + texel(x + 1, y + 1);
+            Rgba((0.25 * sum).to_array())
+        });
+        levels.push(next);
+    }
+
+    levels
+}
+
+/// Rotation by `angles.x`, `angles.y` and `angles.z` radians about the `x`, `y` and `z` axes, in that order.
+pub fn rotation(angles: Vec3) -> Mat3 {
+    Mat3::from_rotation_z(angles.z) * Mat3::from_rotation_y(angles.y) * Mat3::from_rotation_x(angles.x)
+}
+
+/// Direction through the point `(sc, tc)` in `[-1, 1]²` of a cubemap face, with `tc` increasing downwards.
+fn face_direction(face: usize, sc: f32, tc: f32) -> Vec3 {
+    match face {
+        0 => Vec3::new(1.0, -tc, -sc),
+        1 => Vec3::new(-1.0, -tc, sc),
+        2 => Vec3::new(sc, 1.0, tc),
+        3 => Vec3::new(sc, -1.0, -tc),
+        4 => Vec3::new(sc, -tc, 1.0),
+        _ => Vec3::new(-sc, -tc, -1.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+    use image::{Rgba, Rgba32FImage};
+
+    use super::{faces_from_equirectangular, mip_chain, validate_faces};
+    use crate::cpu::CubeMap;
+    use crate::error::Error;
+
+    #[test]
+    fn faces_must_be_square_and_equal() {
+        let mut faces = std::array::from_fn(|_| Rgba32FImage::new(4, 4));
+        assert!(validate_faces(&faces).is_ok());
+
+        faces[2] = Rgba32FImage::new(4, 2);
+        assert!(matches!(
+            validate_faces(&faces),
+            Err(Error::InvalidSkyboxFace { face: "top", .. })
+        ));
+
+        faces[2] = Rgba32FImage::new(8, 8);
+        assert!(validate_faces(&faces).is_err());
+    }
+
+    #[test]
+    fn empty_panoramas_are_rejected() {
+        for (width, height) in [(0, 0), (0, 4), (8, 0)] {
+            assert!(matches!(
+                faces_from_equirectangular(&Rgba32FImage::new(width, height)),
+                Err(Error::EmptyPanorama { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn missing_faces_are_listed() {
+        let error = super::load_faces(std::path::Path::new("images")).unwrap_err();
+        assert!(matches!(error, Error::MissingSkyboxFaces { ref faces, .. } if faces.len() == 6));
+
+        assert!(super::load_faces(std::path::Path::new("images/sky2")).is_ok());
+    }
+
+    #[test]
+    fn mip_chain_averages_down_to_one_texel() {
+        let face = Rgba32FImage::from_fn(5, 4, |x, _| Rgba([x as f32, 1.0, 0.0, 1.0]));
+        let levels = mip_chain(&face);
+
+        let sizes: Vec<_> = levels.iter().map(|level| level.dimensions()).collect();
+        assert_eq!(sizes, [(5, 4), (2, 2), (1, 1)]);
+        assert_eq!(levels[1].get_pixel(1, 0).0, [2.5, 1.0, 0.0, 1.0]);
+        assert_eq!(levels[2].get_pixel(0, 0).0, [1.5, 1.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn equirectangular_poles_lie_on_the_spin_axis() {
+        // Red in the top half of the panorama and blue in the bottom half
+        let panorama = Rgba32FImage::from_fn(64, 32, |_, y| {
+            if y < 16 {
+                Rgba([1.0, 0.0, 0.0, 1.0])
+            } else {
+                Rgba([0.0, 0.0, 1.0, 1.0])
+            }
+        });
+        let sky = CubeMap::new(faces_from_equirectangular(&panorama).unwrap());
+
+        assert_eq!(sky.sample_level(Vec3::Z, 0.0), Vec3::X);
+        assert_eq!(sky.sample_level(-Vec3::Z, 0.0), Vec3::Z);
+        assert_eq!(sky.sample_level(Vec3::new(1.0, 0.5, 0.2), 0.0), Vec3::X);
+    }
+
+    #[test]
+    fn equirectangular_centre_faces_x() {
+        // Green in the middle column, where the longitude is zero
+        let panorama = Rgba32FImage::from_fn(64, 32, |x, _| {
+            if (28..36).contains(&x) {
+                Rgba([0.0, 1.0, 0.0, 1.0])
+            } else {
+                Rgba([0.0, 0.0, 0.0, 1.0])
+            }
+        });
+        let sky = CubeMap::new(faces_from_equirectangular(&panorama).unwrap());
+
+        assert_eq!(sky.sample_level(Vec3::X, 0.0), Vec3::Y);
+        assert_eq!(sky.sample_level(-Vec3::X, 0.0), Vec3::ZERO);
+    }
+}
@@ -1,30 +1,53 @@
-use bytemuck::Zeroable;
-use glam::Mat4;
+use std::sync::{Arc, Mutex};
+
+use half::f16;
+use image::Rgba32FImage;
 use wgpu::util::DeviceExt;
 
-use crate::types::View;
+use crate::error::Error;
+use crate::sky;
+use crate::types::{Disc, Integration, Spacetime, View};
+
+/// Size in bytes of an `Rgba32Float` texel.
+const PIXEL_SIZE: u32 = 4 * std::mem::size_of::<f32>() as u32;
 
 pub struct State {
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
-    pub target: wgpu::Texture,
     pub output_staging_buffer: wgpu::Buffer,
-    pub scale_factor: f32,
 
     pub render_pipeline: wgpu::RenderPipeline,
-    pub view: View,
     pub view_buffer: wgpu::Buffer,
+    pub spacetime_buffer: wgpu::Buffer,
+    pub integration_buffer: wgpu::Buffer,
+    pub disc_buffer: wgpu::Buffer,
     pub view_bind_group: wgpu::BindGroup,
     pub last_frame_textures: [wgpu::Texture; 2],
     pub last_frame_views: [wgpu::TextureView; 2],
     pub last_frame_bind_groups: [wgpu::BindGroup; 2],
-    pub sky_texture: wgpu::Texture,
+    pub sky_bind_group_layout: wgpu::BindGroupLayout,
     pub sky_sampler: wgpu::Sampler,
     pub sky_bind_group: wgpu::BindGroup,
+
+    pub diagnostics_pipeline: wgpu::RenderPipeline,
+    pub diagnostics_bind_group_layout: wgpu::BindGroupLayout,
+    pub diagnostics: Option<DiagnosticsTargets>,
+
+    /// Reason the device was lost, set by its device lost callback.
+    lost: Arc<Mutex<Option<String>>>,
+}
+
+/// Render targets for the diagnostic mode, created the first time it is enabled.
+pub struct DiagnosticsTargets {
+    pub textures: [wgpu::Texture; 2],
+    pub views: [wgpu::TextureView; 2],
+    pub bind_groups: [wgpu::BindGroup; 2],
+    pub staging_buffer: wgpu::Buffer,
 }
 
 impl State {
-    pub async fn new(instance: &wgpu::Instance, texture_data: &Vec<u8>, width: u32, height: u32) -> Self {
+    /// Creates the GPU state, failing if no suitable adapter is available or the device rejects any resource.
+    pub async fn new(instance: &wgpu::Instance, width: u32, height: u32) -> Result<Self, Error> {
         let power_pref = wgpu::PowerPreference::default();
         let adapter = instance
             .request_adapter(&wgpu::RequestAdapterOptions {
@@ -33,7 +56,7 @@ impl State {
                 compatible_surface: None,
             })
             .await
-            .expect("failed to find an appropriate adapter.");
+            .ok_or(Error::NoAdapter)?;
 
         let features = wgpu::Features::empty();
         let (device, queue) = adapter
@@ -47,7 +70,15 @@ impl State {
                 None,
             )
             .await
-            .expect("failed to create device.");
+            .map_err(Error::RequestDevice)?;
+
+        let lost = Arc::new(Mutex::new(None));
+        let reason = Arc::clone(&lost);
+        device.set_device_lost_callback(move |kind, message| {
+            *reason.lock().unwrap() = Some(format!("{message} ({kind:?})"));
+        });
+
+        let resources = ErrorScope::new(&device);
 
         let target = device.create_texture(&wgpu::TextureDescriptor {
             label: Some("render_target"),
@@ -66,14 +97,16 @@ impl State {
 
         let output_staging_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("output_staging_buffer"),
-            size: texture_data.capacity() as u64,
+            size: (padded_bytes_per_row(width) * height) as u64,
             usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
             mapped_at_creation: false,
         });
 
-        let scale_factor: f32 = 1.0;
-
+        let shader = ErrorScope::new(&device);
         let pathtrace_shader = device.create_shader_module(wgpu::include_wgsl!("pathtrace.wgsl"));
+        if let Some(error) = shader.finish().await {
+            return Err(Error::Pipeline(error));
+        }
 
         let last_frame_textures = [
             device.create_texture(&wgpu::TextureDescriptor {
@@ -142,101 +175,101 @@ impl State {
             }),
         ];
 
-        let view = View {
-            resolution: [width, height],
-            camera: Mat4::IDENTITY.to_cols_array(),
-            focal_length: 1.5,
-            ..View::zeroed()
-        };
-
-        let view_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        let view_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("view_buffer"),
-            contents: bytemuck::cast_slice(&[view]),
+            size: std::mem::size_of::<View>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let spacetime_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("spacetime_buffer"),
+            size: std::mem::size_of::<Spacetime>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let integration_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("integration_buffer"),
+            size: std::mem::size_of::<Integration>() as u64,
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let disc_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("disc_buffer"),
+            size: std::mem::size_of::<Disc>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
         });
 
         let view_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some("view_layout"),
-            entries: &[wgpu::BindGroupLayoutEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::FRAGMENT,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Uniform,
-                    has_dynamic_offset: false,
-                    min_binding_size: std::num::NonZero::new(std::mem::size_of::<View>() as u64),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: std::num::NonZero::new(std::mem::size_of::<View>() as u64),
+                    },
+                    count: None,
                 },
-                count: None,
-            }],
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: std::num::NonZero::new(std::mem::size_of::<Spacetime>() as u64),
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: std::num::NonZero::new(std::mem::size_of::<Integration>() as u64),
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: std::num::NonZero::new(std::mem::size_of::<Disc>() as u64),
+                    },
+                    count: None,
+                },
+            ],
         });
 
         let view_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("view_bind_group"),
             layout: &view_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: view_buffer.as_entire_binding(),
-            }],
-        });
-
-        let (sky_image_data, sky_im_width, sky_im_height) = {
-            let right = image::load_from_memory(include_bytes!("../images/sky1/right.png"))
-                .unwrap()
-                .to_rgba8();
-            let left = image::load_from_memory(include_bytes!("../images/sky1/left.png"))
-                .unwrap()
-                .to_rgba8();
-            let top = image::load_from_memory(include_bytes!("../images/sky1/top.png"))
-                .unwrap()
-                .to_rgba8();
-            let bottom = image::load_from_memory(include_bytes!("../images/sky1/bottom.png"))
-                .unwrap()
-                .to_rgba8();
-            let front = image::load_from_memory(include_bytes!("../images/sky1/front.png"))
-                .unwrap()
-                .to_rgba8();
-            let back = image::load_from_memory(include_bytes!("../images/sky1/back.png"))
-                .unwrap()
-                .to_rgba8();
-
-            let (sky_im_width, sky_im_height) = right.dimensions();
-            let mut data: Vec<u8> = Vec::new();
-
-            data.extend(right.as_raw());
-            data.extend(left.as_raw());
-            data.extend(top.as_raw());
-            data.extend(bottom.as_raw());
-            data.extend(front.as_raw());
-            data.extend(back.as_raw());
-
-            (data, sky_im_width, sky_im_height)
-        };
-
-        let sky_image_size = wgpu::Extent3d {
-            width: sky_im_width,
-            height: sky_im_height,
-            depth_or_array_layers: 6,
-        };
-
-        let sky_texture = device.create_texture_with_data(
-            &queue,
-            &wgpu::TextureDescriptor {
-                label: Some("sky_texture"),
-                size: sky_image_size,
-                mip_level_count: 1,
-                sample_count: 1,
-                dimension: wgpu::TextureDimension::D2,
-                format: wgpu::TextureFormat::Rgba8Unorm,
-                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-                view_formats: &[],
-            },
-            wgpu::util::TextureDataOrder::LayerMajor,
-            &sky_image_data,
-        );
-
-        let sky_texture_view = sky_texture.create_view(&wgpu::TextureViewDescriptor {
-            label: Some("sky_texture_view"),
-            dimension: Some(wgpu::TextureViewDimension::Cube),
-            ..Default::default()
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: view_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: spacetime_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: integration_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: disc_buffer.as_entire_binding(),
+                },
+            ],
         });
 
         let sky_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
@@ -272,20 +305,13 @@ impl State {
             ],
         });
 
-        let sky_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("sky_bind_group"),
-            layout: &sky_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&sky_texture_view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&sky_sampler),
-                },
-            ],
-        });
+        let sky_bind_group = create_sky_bind_group(
+            &device,
+            &queue,
+            &sky_bind_group_layout,
+            &sky_sampler,
+            &sky::default_faces()?,
+        );
 
         let render_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("render_pipeline_layout"),
@@ -297,6 +323,7 @@ impl State {
             push_constant_ranges: &[],
         });
 
+        let pipelines = ErrorScope::new(&device);
         let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
             label: Some("render_pipeline"),
             layout: Some(&render_pipeline_layout),
@@ -333,22 +360,390 @@ impl State {
             cache: None,
         });
 
-        Self {
+        let diagnostics_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("diagnostics_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let diagnostics_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("diagnostics_pipeline_layout"),
+            bind_group_layouts: &[
+                &diagnostics_bind_group_layout,
+                &view_bind_group_layout,
+                &sky_bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+
+        let diagnostics_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("diagnostics_pipeline"),
+            layout: Some(&diagnostics_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &pathtrace_shader,
+                entry_point: Some("vertex"),
+                buffers: &[],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &pathtrace_shader,
+                entry_point: Some("fragment_diagnostics"),
+                compilation_options: Default::default(),
+                targets: &[
+                    Some(wgpu::ColorTargetState {
+                        format: target.format(),
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    }),
+                    Some(wgpu::ColorTargetState {
+                        format: wgpu::TextureFormat::Rgba32Float,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    }),
+                ],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                conservative: false,
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+            cache: None,
+        });
+
+        if let Some(error) = pipelines.finish().await {
+            return Err(Error::Pipeline(error));
+        }
+        if let Some(error) = resources.finish().await {
+            return Err(Error::Gpu(error));
+        }
+
+        Ok(Self {
             device,
             queue,
-            target,
             output_staging_buffer,
-            scale_factor,
-            view,
             view_buffer,
+            spacetime_buffer,
+            integration_buffer,
+            disc_buffer,
             view_bind_group,
             last_frame_textures,
             last_frame_views,
             last_frame_bind_groups,
             render_pipeline,
-            sky_texture,
+            sky_bind_group_layout,
             sky_sampler,
             sky_bind_group,
+            diagnostics_pipeline,
+            diagnostics_bind_group_layout,
+            diagnostics: None,
+            lost,
+        })
+    }
+
+    /// Fails if the device has been lost, e.g. because the driver was reset.
+    pub fn check_device(&self) -> Result<(), Error> {
+        match self.lost.lock().unwrap().clone() {
+            Some(reason) => Err(Error::DeviceLost(reason)),
+            None => Ok(()),
+        }
+    }
+
+    /// Copies an `Rgba32Float` texture into `buffer` and returns its contents.
+    pub fn read_texture(&self, texture: &wgpu::Texture, buffer: &wgpu::Buffer) -> Result<Vec<u8>, Error> {
+        let scope = ErrorScope::new(&self.device);
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+
+        encoder.copy_texture_to_buffer(
+            wgpu::TexelCopyTextureInfo {
+                texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::TexelCopyBufferInfo {
+                buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row(texture.width())),
+                    rows_per_image: Some(texture.height()),
+                },
+            },
+            texture.size(),
+        );
+
+        self.queue.submit(std::iter::once(encoder.finish()));
+
+        let buffer_slice = buffer.slice(..);
+        let (sender, receiver) = flume::bounded(1);
+        buffer_slice.map_async(wgpu::MapMode::Read, move |r| {
+            let _ = sender.send(r);
+        });
+        self.device.poll(wgpu::Maintain::wait());
+        self.check_device()?;
+        if let Some(error) = pollster::block_on(scope.finish()) {
+            return Err(Error::Gpu(error));
+        }
+
+        // The callback is dropped without being called if the device is lost while mapping
+        pollster::block_on(receiver.recv_async())
+            .unwrap_or(Err(wgpu::BufferAsyncError))
+            .map_err(Error::BufferMapping)?;
+
+        // Strip the padding from the end of each row
+        let row = (texture.width() * PIXEL_SIZE) as usize;
+        let data = buffer_slice
+            .get_mapped_range()
+            .chunks(padded_bytes_per_row(texture.width()) as usize)
+            .take(texture.height() as usize)
+            .flat_map(|padded| &padded[..row])
+            .copied()
+            .collect();
+        buffer.unmap();
+
+        Ok(data)
+    }
+
+    /// Replaces the skybox with cubemap faces in layer order, which must be square and of equal size. The previous
+    /// skybox is kept if the texture cannot be created.
+    pub fn set_sky(&mut self, faces: &[Rgba32FImage; 6]) -> Result<(), Error> {
+        let scope = ErrorScope::new(&self.device);
+        let sky_bind_group = create_sky_bind_group(
+            &self.device,
+            &self.queue,
+            &self.sky_bind_group_layout,
+            &self.sky_sampler,
+            faces,
+        );
+        if let Some(error) = pollster::block_on(scope.finish()) {
+            return Err(Error::Gpu(error));
         }
+
+        self.sky_bind_group = sky_bind_group;
+        Ok(())
     }
+
+    /// Creates the diagnostics render targets if they do not exist yet.
+    pub fn create_diagnostics_targets(&mut self) -> Result<(), Error> {
+        if self.diagnostics.is_some() {
+            return Ok(());
+        }
+
+        let scope = ErrorScope::new(&self.device);
+
+        let size = self.last_frame_textures[0].size();
+
+        let textures = [
+            self.device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("diagnostics_texture_1"),
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba32Float,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::COPY_DST
+                    | wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::COPY_SRC,
+                view_formats: &[wgpu::TextureFormat::Rgba32Float],
+            }),
+            self.device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("diagnostics_texture_2"),
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba32Float,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::COPY_DST
+                    | wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::COPY_SRC,
+                view_formats: &[wgpu::TextureFormat::Rgba32Float],
+            }),
+        ];
+
+        let views = [
+            textures[0].create_view(&wgpu::TextureViewDescriptor::default()),
+            textures[1].create_view(&wgpu::TextureViewDescriptor::default()),
+        ];
+
+        let bind_groups = [
+            self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("diagnostics_bind_group_1"),
+                layout: &self.diagnostics_bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&self.last_frame_views[0]),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(&views[0]),
+                    },
+                ],
+            }),
+            self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("diagnostics_bind_group_2"),
+                layout: &self.diagnostics_bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&self.last_frame_views[1]),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(&views[1]),
+                    },
+                ],
+            }),
+        ];
+
+        let staging_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("diagnostics_staging_buffer"),
+            size: self.output_staging_buffer.size(),
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        if let Some(error) = pollster::block_on(scope.finish()) {
+            return Err(Error::Gpu(error));
+        }
+
+        self.diagnostics = Some(DiagnosticsTargets {
+            textures,
+            views,
+            bind_groups,
+            staging_buffer,
+        });
+
+        Ok(())
+    }
+}
+
+/// Captures the validation and out-of-memory errors raised by a device until [`ErrorScope::finish`], which would
+/// otherwise go to its uncaptured error handler and panic.
+pub struct ErrorScope<'a> {
+    device: &'a wgpu::Device,
+}
+
+impl<'a> ErrorScope<'a> {
+    pub fn new(device: &'a wgpu::Device) -> Self {
+        device.push_error_scope(wgpu::ErrorFilter::OutOfMemory);
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        Self { device }
+    }
+
+    /// Returns the first error raised since the scope was created.
+    pub async fn finish(self) -> Option<wgpu::Error> {
+        let validation = self.device.pop_error_scope().await;
+        let out_of_memory = self.device.pop_error_scope().await;
+        validation.or(out_of_memory)
+    }
+}
+
+/// Size of a row of an `Rgba32Float` texture `width` pixels wide when copied to a buffer, which has to be a multiple
+/// of [`wgpu::COPY_BYTES_PER_ROW_ALIGNMENT`].
+fn padded_bytes_per_row(width: u32) -> u32 {
+    (width * PIXEL_SIZE).next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+}
+
+/// Uploads cubemap faces in layer order to a new texture and binds it with `sampler`.
+fn create_sky_bind_group(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+    sampler: &wgpu::Sampler,
+    faces: &[Rgba32FImage; 6],
+) -> wgpu::BindGroup {
+    let (width, height) = faces[0].dimensions();
+    let mut data: Vec<u8> = Vec::new();
+    let mut mip_level_count = 0;
+
+    // Half floats keep the range of HDR skies while remaining filterable without extra features. Brighter values are
+    // clamped to the largest half float rather than becoming infinite.
+    let max = f16::MAX.to_f32();
+    for face in faces {
+        let levels = sky::mip_chain(face);
+        mip_level_count = levels.len() as u32;
+
+        for level in levels {
+            data.extend(
+                level
+                    .as_raw()
+                    .iter()
+                    .flat_map(|&c| f16::from_f32(c.clamp(-max, max)).to_ne_bytes()),
+            );
+        }
+    }
+
+    let texture = device.create_texture_with_data(
+        queue,
+        &wgpu::TextureDescriptor {
+            label: Some("sky_texture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 6,
+            },
+            mip_level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba16Float,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        },
+        wgpu::util::TextureDataOrder::LayerMajor,
+        &data,
+    );
+
+    let view = texture.create_view(&wgpu::TextureViewDescriptor {
+        label: Some("sky_texture_view"),
+        dimension: Some(wgpu::TextureViewDimension::Cube),
+        ..Default::default()
+    });
+
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("sky_bind_group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            },
+        ],
+    })
 }
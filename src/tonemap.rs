@@ -0,0 +1,280 @@
+//! Tone mapping of the accumulated linear radiance into the displayable range.
+//!
+//! The radiance is first scaled by the exposure, given in stops (EV), and then compressed by one of the
+//! [`ToneMapping`] operators. The exposure can also be derived from the image itself with [`auto_exposure`].
+
+use std::str::FromStr;
+
+use glam::{Mat3, Vec3};
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+
+/// Rec. 709 luminance weights of the linear sRGB primaries.
+const LUMINANCE: Vec3 = Vec3::new(0.2126, 0.7152, 0.0722);
+
+// Input transform of AgX, from linear sRGB into its working space
+#[allow(clippy::excessive_precision)]
+const AGX_INSET: Mat3 = Mat3::from_cols_array(&[
+    0.842479062253094,
+    0.0423282422610123,
+    0.0423756549057051,
+    0.0784335999999992,
+    0.878468636469772,
+    0.0784336,
+    0.0792237451477643,
+    0.0791661274605434,
+    0.879142973793104,
+]);
+// Inverse of the inset, back to (display encoded) sRGB
+#[allow(clippy::excessive_precision)]
+const AGX_OUTSET: Mat3 = Mat3::from_cols_array(&[
+    1.19687900512017,
+    -0.0528968517574562,
+    -0.0529716355144438,
+    -0.0980208811401368,
+    1.15190312990417,
+    -0.0980434501171241,
+    -0.0990297440797205,
+    -0.0989611768448433,
+    1.15107367264116,
+]);
+// Range of exposures around middle grey covered by the log encoding of AgX
+const AGX_MIN_EV: f32 = -12.47393;
+const AGX_MAX_EV: f32 = 4.026069;
+
+fn smoothstep(edge0: Vec3, edge1: Vec3, mut x: Vec3) -> Vec3 {
+    x = Vec3::clamp((x - edge0) / (edge1 - edge0), Vec3::ZERO, Vec3::ONE);
+    x * x * (3.0 - 2.0 * x)
+}
+
+/// Relative luminance of linear sRGB colour.
+pub fn luminance(color: Vec3) -> f32 {
+    color.dot(LUMINANCE)
+}
+
+fn exponential(color: Vec3) -> Vec3 {
+    smoothstep(Vec3::ZERO, Vec3::ONE, 1.0 - Vec3::exp(-color * 1.0))
+}
+
+/// Extended Reinhard on the luminance, which maps `white` to one. An infinite white point gives plain Reinhard.
+fn reinhard(color: Vec3, white: f32) -> Vec3 {
+    let l = luminance(color);
+    if l <= 0.0 {
+        return Vec3::ZERO;
+    }
+
+    let mapped = l * (1.0 + l / (white * white)) / (1.0 + l);
+    color * (mapped / l)
+}
+
+/// Narkowicz's fit of the ACES filmic reference rendering and output transforms.
+fn aces(color: Vec3) -> Vec3 {
+    let x = 0.6 * color;
+    (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)
+}
+
+/// Sobotka's AgX with the default look, using the polynomial fit of its contrast curve.
+fn agx(color: Vec3) -> Vec3 {
+    let x = AGX_INSET * color.max(Vec3::splat(1e-10));
+    let x = (x.log2().clamp(Vec3::splat(AGX_MIN_EV), Vec3::splat(AGX_MAX_EV)) - AGX_MIN_EV) / (AGX_MAX_EV - AGX_MIN_EV);
+
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    let x = 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232;
+
+    // The curve produces display encoded values, undo the 2.2 gamma so that the sRGB encoding can follow
+    (AGX_OUTSET * x).max(Vec3::ZERO).powf(2.2)
+}
+
+/// Operator compressing the accumulated radiance into the displayable range.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ToneMapping {
+    /// Smooth exponential roll-off of bright values.
+    #[default]
+    Exponential,
+    /// No compression, clipping values above one.
+    #[serde(alias = "none")]
+    Linear,
+    /// Reinhard's `L / (1 + L)` on the luminance, which never quite reaches white.
+    Reinhard,
+    /// Reinhard with a white point, above which the luminance is clipped.
+    ReinhardExtended,
+    /// Filmic curve of the ACES reference rendering transform.
+    Aces,
+    /// AgX, which desaturates very bright colours towards white instead of skewing their hue.
+    Agx,
+}
+
+impl ToneMapping {
+    /// Maps linear radiance to linear colour in `[0, 1]`, with the default white point and no exposure.
+    pub fn apply(self, color: Vec3) -> Vec3 {
+        ToneMapper::new(self).apply(color)
+    }
+}
+
+impl FromStr for ToneMapping {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "exponential" => Ok(ToneMapping::Exponential),
+            "linear" | "none" => Ok(ToneMapping::Linear),
+            "reinhard" => Ok(ToneMapping::Reinhard),
+            "reinhard-extended" => Ok(ToneMapping::ReinhardExtended),
+            "aces" => Ok(ToneMapping::Aces),
+            "agx" => Ok(ToneMapping::Agx),
+            _ => Err(Error::UnknownOption {
+                name: "tone mapping",
+                value: s.to_string(),
+            }),
+        }
+    }
+}
+
+/// A tone mapping operator together with its exposure.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ToneMapper {
+    pub operator: ToneMapping,
+    /// Exposure in stops, scaling the radiance by `2^exposure`.
+    pub exposure: f32,
+    /// Exposed luminance mapped to white by [`ToneMapping::ReinhardExtended`].
+    pub white: f32,
+}
+
+impl Default for ToneMapper {
+    fn default() -> Self {
+        Self::new(ToneMapping::default())
+    }
+}
+
+impl ToneMapper {
+    /// Default white point of [`ToneMapping::ReinhardExtended`].
+    pub const WHITE: f32 = 4.0;
+
+    pub fn new(operator: ToneMapping) -> Self {
+        Self {
+            operator,
+            exposure: 0.0,
+            white: Self::WHITE,
+        }
+    }
+
+    /// Sets the exposure in stops.
+    pub fn with_exposure(mut self, exposure: f32) -> Result<Self, Error> {
+        if !exposure.is_finite() {
+            return Err(Error::InvalidParameter {
+                name: "exposure",
+                value: exposure,
+            });
+        }
+
+        self.exposure = exposure;
+        Ok(self)
+    }
+
+    /// Sets the white point of [`ToneMapping::ReinhardExtended`], in exposed luminance.
+    pub fn with_white(mut self, white: f32) -> Result<Self, Error> {
+        if white.is_nan() || white <= 0.0 {
+            return Err(Error::InvalidParameter {
+                name: "white point",
+                value: white,
+            });
+        }
+
+        self.white = white;
+        Ok(self)
+    }
+
+    /// Maps linear radiance to linear colour in `[0, 1]`.
+    pub fn apply(&self, color: Vec3) -> Vec3 {
+        let color = color.max(Vec3::ZERO) * self.exposure.exp2();
+
+        let mapped = match self.operator {
+            ToneMapping::Exponential => exponential(color),
+            ToneMapping::Linear => color,
+            ToneMapping::Reinhard => reinhard(color, f32::INFINITY),
+            ToneMapping::ReinhardExtended => reinhard(color, self.white),
+            ToneMapping::Aces => aces(color),
+            ToneMapping::Agx => agx(color),
+        };
+
+        mapped.clamp(Vec3::ZERO, Vec3::ONE)
+    }
+}
+
+/// Exposure in stops that maps the luminance at `percentile` (in `(0, 100]`) of the lit pixels to one.
+///
+/// Pixels without any radiance, such as the shadow and a black sky, are ignored so that they do not drag the exposure
+/// up. An image without lit pixels gets no exposure.
+pub fn auto_exposure(pixels: &[Vec3], percentile: f32) -> Result<f32, Error> {
+    if !(percentile > 0.0 && percentile <= 100.0) {
+        return Err(Error::InvalidParameter {
+            name: "auto exposure percentile",
+            value: percentile,
+        });
+    }
+
+    let mut luminances: Vec<_> = pixels
+        .iter()
+        .map(|&pixel| luminance(pixel))
+        .filter(|l| l.is_finite() && *l > 0.0)
+        .collect();
+    if luminances.is_empty() {
+        return Ok(0.0);
+    }
+
+    let rank = ((percentile / 100.0 * luminances.len() as f32).ceil() as usize).clamp(1, luminances.len()) - 1;
+    let (_, l, _) = luminances.select_nth_unstable_by(rank, f32::total_cmp);
+    Ok(-l.log2())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPERATORS: [ToneMapping; 6] = [
+        ToneMapping::Exponential,
+        ToneMapping::Linear,
+        ToneMapping::Reinhard,
+        ToneMapping::ReinhardExtended,
+        ToneMapping::Aces,
+        ToneMapping::Agx,
+    ];
+
+    #[test]
+    fn operators_are_monotonic_and_bounded() {
+        for operator in OPERATORS {
+            let mapper = ToneMapper::new(operator);
+            let mut previous = 0.0;
+            for i in 0..=200 {
+                let l = luminance(mapper.apply(Vec3::splat(0.01 * 1.05f32.powi(i))));
+                assert!(l >= previous - 1e-5, "{operator:?} decreases at step {i}");
+                assert!(l <= 1.0, "{operator:?} exceeds one");
+                previous = l;
+            }
+            assert!(luminance(mapper.apply(Vec3::ZERO)) < 1e-3, "{operator:?} lifts black");
+        }
+    }
+
+    #[test]
+    fn exposure_scales_radiance_in_stops() {
+        let mapper = ToneMapper::new(ToneMapping::Linear).with_exposure(-2.0).unwrap();
+        assert_eq!(mapper.apply(Vec3::splat(2.0)), Vec3::splat(0.5));
+
+        let mapper = ToneMapper::new(ToneMapping::ReinhardExtended).with_white(8.0).unwrap();
+        assert!((mapper.apply(Vec3::splat(8.0)) - Vec3::ONE).abs().max_element() < 1e-5);
+    }
+
+    #[test]
+    fn auto_exposure_maps_percentile_to_one() {
+        let mut pixels: Vec<_> = (1..=100).map(|i| Vec3::splat(i as f32 / 25.0)).collect();
+        pixels.extend([Vec3::ZERO; 100]);
+
+        let exposure = auto_exposure(&pixels, 50.0).unwrap();
+        assert!((exposure - -1.0).abs() < 1e-5, "{exposure}");
+        assert!(auto_exposure(&pixels, 0.0).is_err());
+        assert_eq!(auto_exposure(&[Vec3::ZERO], 99.0).unwrap(), 0.0);
+    }
+}
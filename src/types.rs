@@ -9,4 +9,73 @@ pub struct View {
     pub resolution: [u32; 2],
     pub frame_count: u32,
     pub flags: u32,
+    pub seed: u32,
+    pub sky_intensity: f32,
+    pub _padding: [u32; 2],
+    /// Rotates world directions into the frame of the sky texture.
+    pub sky_rotation: [f32; 16],
+    pub star_seed: u32,
+    /// Cells along each edge of a face of the cube on which stars are placed.
+    pub star_grid: u32,
+    /// Probability of a cell containing a star.
+    pub star_probability: f32,
+    /// Flux of the faintest stars, in units of radiance times steradians.
+    pub star_flux: f32,
+    /// Standard deviation of the profile of a star, in radians.
+    pub star_size: f32,
+    pub galaxy_brightness: f32,
+    pub _star_padding: [u32; 2],
+}
+
+#[repr(C, align(16))]
+#[derive(Pod, Zeroable, Clone, Copy)]
+pub struct Spacetime {
+    pub mass: f32,
+    pub spin: f32,
+    pub charge: f32,
+    pub lensing_radius: f32,
+    pub metric: u32,
+    pub horizon: f32,
+    pub photon_orbit: f32,
+    pub max_momentum: f32,
+}
+
+#[repr(C, align(16))]
+#[derive(Pod, Zeroable, Clone, Copy)]
+pub struct Integration {
+    pub method: u32,
+    pub tolerance: f32,
+    pub _padding: [u32; 2],
+}
+
+#[repr(C, align(16))]
+#[derive(Pod, Zeroable, Clone, Copy)]
+pub struct Disc {
+    pub redshift: u32,
+    pub isco: f32,
+    pub isco_energy: f32,
+    pub isco_angular_momentum: f32,
+    pub model: u32,
+    pub outer_radius: f32,
+    pub temperature: f32,
+    pub _padding: u32,
+    pub flux: [f32; 64],
+}
+
+/// Values of all uniform buffers used by the pathtracer, shared by the GPU and CPU backends.
+#[derive(Clone, Copy)]
+pub struct Uniforms {
+    pub view: View,
+    pub spacetime: Spacetime,
+    pub integration: Integration,
+    pub disc: Disc,
+}
+
+impl PartialEq for Uniforms {
+    fn eq(&self, other: &Self) -> bool {
+        bytemuck::bytes_of(&self.view) == bytemuck::bytes_of(&other.view)
+            && bytemuck::bytes_of(&self.spacetime) == bytemuck::bytes_of(&other.spacetime)
+            && bytemuck::bytes_of(&self.integration) == bytemuck::bytes_of(&other.integration)
+            && bytemuck::bytes_of(&self.disc) == bytemuck::bytes_of(&other.disc)
+    }
 }